@@ -105,7 +105,9 @@ def stress_test_loop():
             # imesde calculates the distance between the query and all ingested flights.
             start_search = time.perf_counter()
             search_query = "dangerous high speed at very low altitude or emergency squawk"
-            results = db.search(search_query, k=5)
+            # search_range returns every match scoring at least MIN_SCORE (at most 5 here).
+            # The cutoff is inclusive: a score equal to MIN_SCORE counts as a match.
+            results = db.search_range(search_query, MIN_SCORE, max_results=5)
             end_search = time.perf_counter()
 
             # --- LOGGING STATISTICS ---
@@ -119,12 +121,10 @@ def stress_test_loop():
             print(f"  - Search Latency: {search_latency:.4f}s")
             
             # 5. ALERTING
-            # Filter results by similarity score. 
-            # If score > MIN_SCORE, it's a semantic match for "danger".
-            matches = [r for r in results if r[1] > MIN_SCORE]
-            if matches:
-                print(f"🔥 Found {len(matches)} potential anomalies!")
-                autonomous_alert(matches[0][0], len(matches))
+            # Every result already scored at least MIN_SCORE: it's a semantic match for "danger".
+            if results:
+                print(f"🔥 Found {len(results)} potential anomalies!")
+                autonomous_alert(results[0][0], len(results))
             else:
                 print("✅ Airspace Scan: No anomalies detected.")
 
@@ -1,128 +1,476 @@
 use pyo3::prelude::*;
-use ::imesde::engine::{ShardedCircularBuffer, DEFAULT_NUM_SHARDS, DEFAULT_SHARD_SIZE};
-use ::imesde::embedder::TextEmbedder;
-use ::imesde::models::VectorRecord;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::types::{PyBool, PyDict, PyFloat, PyInt, PyList, PyString};
+use ::imesde::engine::{SearchOptions, ShardedCircularBuffer, VectorStorage, DEFAULT_NUM_SHARDS, DEFAULT_OVERSAMPLE, DEFAULT_SHARD_SIZE};
+#[cfg(feature = "embedder")]
+use ::imesde::embedder::{ChunkMode, Chunking, ModelConfig, Pooling, TextEmbedder, DEFAULT_NUM_SESSIONS};
+use ::imesde::batching::{BatchConfig, BatchingEmbedder};
+use ::imesde::cache::{CachingEmbedder, EmbeddingCache, DEFAULT_CACHE_SIZE};
+use ::imesde::embedding::{document_records, Embedder, HashEmbedder};
+use ::imesde::filter::Filter;
+use ::imesde::search::{Metric, ScoringMode};
+use ::imesde::models::{chunk_id, timestamp_from_secs_f64, AttributeValue, Attributes, VectorRecord};
+use ::imesde::Error;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
+
+create_exception!(imesde, ImesdeError, PyException, "Base class for all imesde errors.");
+create_exception!(imesde, ModelLoadError, ImesdeError, "The ONNX model or tokenizer could not be loaded.");
+create_exception!(imesde, EmbeddingError, ImesdeError, "Tokenization or model inference failed.");
+create_exception!(imesde, ConfigError, ImesdeError, "Invalid engine or embedder configuration.");
+create_exception!(imesde, VectorError, ImesdeError, "A vector was rejected by the engine.");
+
+fn to_py_err(err: Error) -> PyErr {
+    let msg = err.to_string();
+    match err {
+        Error::TokenizerLoad(_) => ModelLoadError::new_err(msg),
+        #[cfg(feature = "embedder")]
+        Error::ModelLoad(_) => ModelLoadError::new_err(msg),
+        Error::Tokenization(_) | Error::Embedder(_) | Error::MissingOutput(_) => EmbeddingError::new_err(msg),
+        #[cfg(feature = "embedder")]
+        Error::Inference(_) | Error::Shape(_) => EmbeddingError::new_err(msg),
+        Error::InvalidConfig(_) => ConfigError::new_err(msg),
+        Error::InvalidVector(_) | Error::DimensionMismatch { .. } => VectorError::new_err(msg),
+        _ => EmbeddingError::new_err(msg),
+    }
+}
+
+// (text, score, similarity, recency)
+type ScoredHit = (String, f32, f32, Option<f32>);
 
 #[pyclass]
 struct PyImesde {
     buffer: Arc<ShardedCircularBuffer>,
-    embedder: Arc<TextEmbedder>,
+    embedder: Arc<dyn Embedder>,
+    cache: Option<Arc<EmbeddingCache>>,
     counter: Arc<AtomicUsize>,
 }
 
+impl PyImesde {
+    fn from_embedder(
+        embedder: Arc<dyn Embedder>,
+        cache_size: Option<usize>,
+        batching: Option<BatchConfig>,
+        buffer: BufferOptions,
+    ) -> PyResult<Self> {
+        let embedder: Arc<dyn Embedder> = match batching {
+            Some(config) => Arc::new(BatchingEmbedder::new(embedder, config).map_err(to_py_err)?),
+            None => embedder,
+        };
+        // The cache wraps the batching stage, so hits return without waiting for a batch.
+        let cache_size = cache_size.unwrap_or(DEFAULT_CACHE_SIZE);
+        let cache = (cache_size > 0).then(|| Arc::new(EmbeddingCache::new(cache_size)));
+        let embedder: Arc<dyn Embedder> = match &cache {
+            Some(cache) => Arc::new(CachingEmbedder::new(embedder, Arc::clone(cache))),
+            None => embedder,
+        };
+
+        // Fixing the buffer to the embedder's dimension rejects raw vectors from another
+        // model, and embedders whose output does not match the `dim` they report.
+        Ok(Self {
+            buffer: buffer.build(embedder.dim())?,
+            embedder,
+            cache,
+            counter: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+}
+
 #[pymethods]
 impl PyImesde {
+    #[cfg(feature = "embedder")]
     #[new]
-    #[pyo3(signature = (model_path, tokenizer_path, num_shards=None, shard_size=None))]
+    #[pyo3(signature = (model_path, tokenizer_path, num_shards=None, shard_size=None, max_age_secs=None, output_name=None, use_token_type_ids=None, pooling=None, max_length=None, chunk_mode=None, chunk_overlap=None, query_prefix=None, document_prefix=None, num_sessions=None, intra_threads=None, inter_threads=None, max_batch_size=None, max_batch_wait_ms=None, cache_size=None, truncate_dim=None, storage=None, rerank=None, metric=None))]
+    #[allow(clippy::too_many_arguments)]
     fn new(
+        py: Python<'_>,
         model_path: &str,
         tokenizer_path: &str,
         num_shards: Option<usize>,
         shard_size: Option<usize>,
+        max_age_secs: Option<f64>,
+        output_name: Option<String>,
+        use_token_type_ids: Option<bool>,
+        pooling: Option<&str>,
+        max_length: Option<usize>,
+        chunk_mode: Option<&str>,
+        chunk_overlap: Option<usize>,
+        query_prefix: Option<String>,
+        document_prefix: Option<String>,
+        num_sessions: Option<usize>,
+        intra_threads: Option<usize>,
+        inter_threads: Option<usize>,
+        max_batch_size: Option<usize>,
+        max_batch_wait_ms: Option<f64>,
+        cache_size: Option<usize>,
+        truncate_dim: Option<usize>,
+        storage: Option<&str>,
+        rerank: Option<usize>,
+        metric: Option<&str>,
     ) -> PyResult<Self> {
-        let ns = num_shards.unwrap_or(DEFAULT_NUM_SHARDS);
-        let ss = shard_size.unwrap_or(DEFAULT_SHARD_SIZE);
-        Ok(Self {
-            buffer: Arc::new(ShardedCircularBuffer::new(ns, ss)),
-            embedder: Arc::new(TextEmbedder::new(model_path, tokenizer_path)),
-            counter: Arc::new(AtomicUsize::new(0)),
-        })
+        let buffer = BufferOptions::parse(num_shards, shard_size, max_age_secs, truncate_dim, storage, rerank, metric)?;
+        // One batch in flight per session keeps every session busy.
+        let batching = batch_config(max_batch_size, max_batch_wait_ms)?
+            .map(|config| BatchConfig { workers: num_sessions.unwrap_or(DEFAULT_NUM_SESSIONS), ..config });
+        let pooling = pooling.map(str::parse::<Pooling>).transpose().map_err(to_py_err)?;
+        let chunking = match (chunk_mode, chunk_overlap) {
+            (Some(mode), overlap) => Some(Chunking {
+                overlap: overlap.unwrap_or(DEFAULT_CHUNK_OVERLAP),
+                mode: mode.parse::<ChunkMode>().map_err(to_py_err)?,
+            }),
+            (None, Some(_)) => return Err(PyValueError::new_err("chunk_overlap requires chunk_mode")),
+            (None, None) => None,
+        };
+        let config = ModelConfig {
+            token_type_ids: use_token_type_ids,
+            output_name,
+            pooling,
+            max_length,
+            chunking,
+            query_prefix,
+            document_prefix,
+            num_sessions,
+            intra_threads,
+            inter_threads,
+        };
+        let embedder = py.allow_threads(|| TextEmbedder::with_config(model_path, tokenizer_path, config))
+            .map_err(to_py_err)?;
+
+        Self::from_embedder(Arc::new(embedder), cache_size, batching, buffer)
     }
 
-    fn ingest(&self, py: Python<'_>, text: String) -> PyResult<()> {
+    /// Creates an engine around any embedder: a `HashEmbedder`, or a Python object with an
+    /// `embed(text) -> list[float]` method and optionally a `dim` attribute.
+    #[staticmethod]
+    #[pyo3(signature = (embedder, num_shards=None, shard_size=None, max_age_secs=None, max_batch_size=None, max_batch_wait_ms=None, cache_size=None, truncate_dim=None, storage=None, rerank=None, metric=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn with_embedder(
+        embedder: &Bound<'_, PyAny>,
+        num_shards: Option<usize>,
+        shard_size: Option<usize>,
+        max_age_secs: Option<f64>,
+        max_batch_size: Option<usize>,
+        max_batch_wait_ms: Option<f64>,
+        cache_size: Option<usize>,
+        truncate_dim: Option<usize>,
+        storage: Option<&str>,
+        rerank: Option<usize>,
+        metric: Option<&str>,
+    ) -> PyResult<Self> {
+        let batching = batch_config(max_batch_size, max_batch_wait_ms)?;
+        let buffer = BufferOptions::parse(num_shards, shard_size, max_age_secs, truncate_dim, storage, rerank, metric)?;
+        let embedder: Arc<dyn Embedder> = match embedder.downcast::<PyHashEmbedder>() {
+            Ok(hash) => hash.borrow().inner.clone(),
+            Err(_) => Arc::new(PyObjectEmbedder::new(embedder)?),
+        };
+        Self::from_embedder(embedder, cache_size, batching, buffer)
+    }
+
+    #[getter]
+    fn dim(&self) -> usize {
+        self.embedder.dim()
+    }
+
+    /// Embedding cache counters as a dict, or `None` when the cache is disabled.
+    fn cache_stats<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDict>>> {
+        let Some(cache) = &self.cache else { return Ok(None) };
+        let stats = cache.stats();
+        let dict = PyDict::new(py);
+        dict.set_item("hits", stats.hits)?;
+        dict.set_item("misses", stats.misses)?;
+        dict.set_item("len", stats.len)?;
+        dict.set_item("capacity", stats.capacity)?;
+        Ok(Some(dict))
+    }
+
+    #[pyo3(signature = (text, attributes=None, timestamp=None))]
+    fn ingest(
+        &self,
+        py: Python<'_>,
+        text: String,
+        attributes: Option<&Bound<'_, PyDict>>,
+        timestamp: Option<f64>,
+    ) -> PyResult<()> {
+        let attributes = to_attributes(attributes)?;
+        let timestamp = timestamp.map(to_timestamp).transpose()?;
         py.allow_threads(|| {
-            let vector = self.embedder.embed(&text);
+            let chunks = self.embedder.embed_documents(vec![text])?.remove(0);
             let id = self.counter.fetch_add(1, Ordering::SeqCst);
-            let record = VectorRecord::new(
-                format!("log_{}", id),
-                vector,
-                text,
-            );
-            self.buffer.insert(record);
-        });
-        Ok(())
+            for record in document_records(format!("log_{}", id), chunks, timestamp, attributes) {
+                self.buffer.insert(record)?;
+            }
+            Ok(())
+        }).map_err(to_py_err)
     }
 
-    fn ingest_batch(&self, py: Python<'_>, texts: Vec<String>) -> PyResult<()> {
+    #[pyo3(signature = (texts, attributes=None, timestamps=None))]
+    fn ingest_batch(
+        &self,
+        py: Python<'_>,
+        texts: Vec<String>,
+        attributes: Option<Vec<Bound<'_, PyDict>>>,
+        timestamps: Option<Vec<f64>>,
+    ) -> PyResult<()> {
+        let attributes = to_attributes_list(attributes, texts.len())?;
+        let timestamps = to_timestamp_list(timestamps, texts.len())?;
         py.allow_threads(|| {
             use rayon::prelude::*;
             let chunk_size = 128;
-            texts.par_chunks(chunk_size).for_each(|chunk| {
+            texts.par_chunks(chunk_size)
+                .zip(attributes.par_chunks(chunk_size))
+                .zip(timestamps.par_chunks(chunk_size))
+                .try_for_each(|((chunk, attrs), stamps)| {
                 let chunk_vec: Vec<String> = chunk.to_vec();
-                let vectors = self.embedder.embed_batch(chunk_vec);
-                
-                for (i, vector) in vectors.into_iter().enumerate() {
+                let documents = self.embedder.embed_documents(chunk_vec)?;
+
+                for (i, chunks) in documents.into_iter().enumerate() {
                     let id = self.counter.fetch_add(1, Ordering::SeqCst);
-                    let text = &chunk[i];
-                    let record = VectorRecord::new(
-                        format!("log_{}", id),
-                        vector,
-                        text.clone(),
-                    );
-                    self.buffer.insert(record);
+                    let records = document_records(format!("log_{}", id), chunks, stamps[i], attrs[i].clone());
+                    for record in records {
+                        self.buffer.insert(record)?;
+                    }
                 }
-            });
-        });
-        Ok(())
+                Ok(())
+            })
+        }).map_err(to_py_err)
     }
 
-    fn ingest_raw(&self, py: Python<'_>, vector: Vec<f32>, text: String) -> PyResult<()> {
+    #[pyo3(signature = (vector, text, attributes=None, timestamp=None))]
+    fn ingest_raw(
+        &self,
+        py: Python<'_>,
+        vector: Vec<f32>,
+        text: String,
+        attributes: Option<&Bound<'_, PyDict>>,
+        timestamp: Option<f64>,
+    ) -> PyResult<()> {
+        let attributes = to_attributes(attributes)?;
+        let timestamp = timestamp.map(to_timestamp).transpose()?;
         py.allow_threads(|| {
             let id = self.counter.fetch_add(1, Ordering::SeqCst);
-            let record = VectorRecord::new(
+            let record = new_record(
                 format!("log_{}", id),
                 vector,
                 text,
-            );
-            self.buffer.insert(record);
-        });
-        Ok(())
+                timestamp,
+            ).with_attributes(attributes);
+            self.buffer.insert(record)
+        }).map_err(to_py_err)
     }
 
-    fn ingest_batch_raw(&self, py: Python<'_>, vectors: Vec<Vec<f32>>, texts: Vec<String>) -> PyResult<()> {
+    #[pyo3(signature = (vectors, texts, attributes=None, timestamps=None))]
+    fn ingest_batch_raw(
+        &self,
+        py: Python<'_>,
+        vectors: Vec<Vec<f32>>,
+        texts: Vec<String>,
+        attributes: Option<Vec<Bound<'_, PyDict>>>,
+        timestamps: Option<Vec<f64>>,
+    ) -> PyResult<()> {
         if vectors.len() != texts.len() {
-            return Err(pyo3::exceptions::PyValueError::new_err("Vectors and texts must have the same length"));
+            return Err(PyValueError::new_err("Vectors and texts must have the same length"));
         }
+        let attributes = to_attributes_list(attributes, texts.len())?;
+        let timestamps = to_timestamp_list(timestamps, texts.len())?;
         py.allow_threads(|| {
             use rayon::prelude::*;
             // Parallel ingestion directly in Rust binding to avoid Python loop overhead
-            vectors.into_par_iter().zip(texts.into_par_iter()).for_each(|(vector, text)| {
+            vectors.into_par_iter()
+                .zip(texts.into_par_iter())
+                .zip(attributes.into_par_iter())
+                .zip(timestamps.into_par_iter())
+                .try_for_each(|(((vector, text), attrs), timestamp)| {
                 let id = self.counter.fetch_add(1, Ordering::SeqCst);
-                let record = VectorRecord::new(
+                let record = new_record(
                     format!("log_{}", id),
                     vector,
                     text,
-                );
-                self.buffer.insert(record);
-            });
-        });
-        Ok(())
+                    timestamp,
+                ).with_attributes(attrs);
+                self.buffer.insert(record)
+            })
+        }).map_err(to_py_err)
     }
 
-    fn search(&self, py: Python<'_>, query: String, k: usize) -> PyResult<Vec<(String, f32)>> {
+    #[pyo3(signature = (query, k, filter=None, since=None, until=None, half_life_secs=None, recency_weight=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn search(
+        &self,
+        py: Python<'_>,
+        query: String,
+        k: usize,
+        filter: Option<&Bound<'_, PyDict>>,
+        since: Option<f64>,
+        until: Option<f64>,
+        half_life_secs: Option<f64>,
+        recency_weight: Option<f32>,
+    ) -> PyResult<Vec<(String, f32)>> {
+        let filter = filter.map(to_filter).transpose()?;
+        let options = search_options(filter.as_ref(), since, until, half_life_secs, recency_weight)?;
         let results = py.allow_threads(|| {
-            let query_vec = self.embedder.embed(&query);
-            self.buffer.search(&query_vec, k)
-        });
-        
+            let query_vec = self.embedder.embed_query(&query)?;
+            self.buffer.search_with(&query_vec, k, &options)
+        }).map_err(to_py_err)?;
+
         let py_results = results.into_iter()
             .map(|(record, score)| (record.metadata.clone(), score))
             .collect();
-            
+
         Ok(py_results)
     }
 
+    /// Returns every record scoring at least `min_score`, best first.
+    #[pyo3(signature = (query, min_score, max_results=None, filter=None, since=None, until=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn search_range(
+        &self,
+        py: Python<'_>,
+        query: String,
+        min_score: f32,
+        max_results: Option<usize>,
+        filter: Option<&Bound<'_, PyDict>>,
+        since: Option<f64>,
+        until: Option<f64>,
+    ) -> PyResult<Vec<(String, f32)>> {
+        let filter = filter.map(to_filter).transpose()?;
+        let options = search_options(filter.as_ref(), since, until, None, None)?.with_min_score(min_score);
+        let results = py.allow_threads(|| {
+            let query_vec = self.embedder.embed_query(&query)?;
+            self.buffer.search_with(&query_vec, max_results.unwrap_or(usize::MAX), &options)
+        }).map_err(to_py_err)?;
+
+        Ok(results.into_iter()
+            .map(|(record, score)| (record.metadata.clone(), score))
+            .collect())
+    }
+
+    /// Returns `(text, score, similarity, recency)` tuples; `recency` is `None`
+    /// unless `half_life_secs` is given.
+    #[pyo3(signature = (query, k, filter=None, since=None, until=None, half_life_secs=None, recency_weight=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn search_scored(
+        &self,
+        py: Python<'_>,
+        query: String,
+        k: usize,
+        filter: Option<&Bound<'_, PyDict>>,
+        since: Option<f64>,
+        until: Option<f64>,
+        half_life_secs: Option<f64>,
+        recency_weight: Option<f32>,
+    ) -> PyResult<Vec<ScoredHit>> {
+        let filter = filter.map(to_filter).transpose()?;
+        let options = search_options(filter.as_ref(), since, until, half_life_secs, recency_weight)?;
+        let hits = py.allow_threads(|| {
+            let query_vec = self.embedder.embed_query(&query)?;
+            self.buffer.search_scored(&query_vec, k, &options)
+        }).map_err(to_py_err)?;
+
+        Ok(hits.into_iter()
+            .map(|hit| (hit.record.metadata.clone(), hit.score, hit.similarity, hit.recency))
+            .collect())
+    }
+
     fn embed_query(&self, py: Python<'_>, text: String) -> PyResult<Vec<f32>> {
-        let vector = py.allow_threads(|| {
-            self.embedder.embed(&text)
-        });
-        Ok(vector)
+        py.allow_threads(|| {
+            self.embedder.embed_query(&text)
+        }).map_err(to_py_err)
+    }
+
+    fn get(&self, id: &str) -> PyResult<Option<String>> {
+        Ok(self.buffer.get(id).map(|record| record.metadata.clone()))
     }
 
-    fn search_raw(&self, query_vector: Vec<f32>, k: usize) -> PyResult<Vec<(String, f32)>> {
-        let results = self.buffer.search(&query_vector, k);
+    fn remove(&self, id: &str) -> PyResult<bool> {
+        Ok(self.buffer.remove(id))
+    }
+
+    #[pyo3(signature = (id, text, attributes=None, timestamp=None))]
+    fn upsert(
+        &self,
+        py: Python<'_>,
+        id: String,
+        text: String,
+        attributes: Option<&Bound<'_, PyDict>>,
+        timestamp: Option<f64>,
+    ) -> PyResult<()> {
+        let attributes = to_attributes(attributes)?;
+        let timestamp = timestamp.map(to_timestamp).transpose()?;
+        py.allow_threads(|| {
+            let chunks = self.embedder.embed_documents(vec![text])?.remove(0);
+            let records = document_records(id.clone(), chunks, timestamp, attributes);
+            let num_chunks = if records.len() > 1 {
+                self.buffer.remove(&id);
+                records.len()
+            } else {
+                0
+            };
+            for record in records {
+                self.buffer.upsert(record)?;
+            }
+            // Drop the chunks left over from a longer previous version of the document.
+            (num_chunks..).take_while(|&i| self.buffer.remove(&chunk_id(&id, i))).count();
+            Ok(())
+        }).map_err(to_py_err)
+    }
+
+    #[pyo3(signature = (id, vector, text, attributes=None, timestamp=None))]
+    fn upsert_raw(
+        &self,
+        py: Python<'_>,
+        id: String,
+        vector: Vec<f32>,
+        text: String,
+        attributes: Option<&Bound<'_, PyDict>>,
+        timestamp: Option<f64>,
+    ) -> PyResult<()> {
+        let attributes = to_attributes(attributes)?;
+        let timestamp = timestamp.map(to_timestamp).transpose()?;
+        py.allow_threads(|| {
+            self.buffer.upsert(new_record(id, vector, text, timestamp).with_attributes(attributes))
+        }).map_err(to_py_err)
+    }
+
+    #[pyo3(signature = (query_vector, min_score, max_results=None, filter=None, since=None, until=None))]
+    fn search_range_raw(
+        &self,
+        query_vector: Vec<f32>,
+        min_score: f32,
+        max_results: Option<usize>,
+        filter: Option<&Bound<'_, PyDict>>,
+        since: Option<f64>,
+        until: Option<f64>,
+    ) -> PyResult<Vec<(String, f32)>> {
+        let filter = filter.map(to_filter).transpose()?;
+        let options = search_options(filter.as_ref(), since, until, None, None)?.with_min_score(min_score);
+        let results = self.buffer.search_with(&query_vector, max_results.unwrap_or(usize::MAX), &options)
+            .map_err(to_py_err)?;
+        Ok(results.into_iter()
+            .map(|(record, score)| (record.metadata.clone(), score))
+            .collect())
+    }
+
+    fn evict_expired(&self, py: Python<'_>) -> PyResult<usize> {
+        Ok(py.allow_threads(|| self.buffer.evict_expired()))
+    }
+
+    #[pyo3(signature = (query_vector, k, filter=None, since=None, until=None, half_life_secs=None, recency_weight=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn search_raw(
+        &self,
+        query_vector: Vec<f32>,
+        k: usize,
+        filter: Option<&Bound<'_, PyDict>>,
+        since: Option<f64>,
+        until: Option<f64>,
+        half_life_secs: Option<f64>,
+        recency_weight: Option<f32>,
+    ) -> PyResult<Vec<(String, f32)>> {
+        let filter = filter.map(to_filter).transpose()?;
+        let options = search_options(filter.as_ref(), since, until, half_life_secs, recency_weight)?;
+        let results = self.buffer.search_with(&query_vector, k, &options).map_err(to_py_err)?;
         let py_results = results.into_iter()
             .map(|(record, score)| (record.metadata.clone(), score))
             .collect();
@@ -130,8 +478,337 @@ impl PyImesde {
     }
 }
 
+/// Deterministic, model-free embedder for tests and offline use. Texts sharing words
+/// get similar vectors; it has no semantic understanding.
+#[pyclass(name = "HashEmbedder")]
+struct PyHashEmbedder {
+    inner: Arc<HashEmbedder>,
+}
+
+#[pymethods]
+impl PyHashEmbedder {
+    #[new]
+    #[pyo3(signature = (dim=384, seed=0))]
+    fn new(dim: usize, seed: u64) -> PyResult<Self> {
+        let inner = HashEmbedder::with_seed(dim, seed).map_err(to_py_err)?;
+        Ok(Self { inner: Arc::new(inner) })
+    }
+
+    #[getter]
+    fn dim(&self) -> usize {
+        self.inner.dim()
+    }
+
+    fn embed(&self, text: &str) -> PyResult<Vec<f32>> {
+        self.inner.embed(text).map_err(to_py_err)
+    }
+}
+
+// Adapts a Python object with an `embed(text)` method. The GIL is taken for each call.
+struct PyObjectEmbedder {
+    object: Py<PyAny>,
+    dim: usize,
+}
+
+impl PyObjectEmbedder {
+    fn new(object: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if !object.hasattr("embed")? {
+            return Err(PyValueError::new_err("embedder must have an embed(text) method"));
+        }
+        let dim = match object.getattr("dim") {
+            Ok(dim) => dim.extract::<usize>()?,
+            Err(_) => object.call_method1("embed", ("test",))?.extract::<Vec<f32>>()?.len(),
+        };
+        Ok(Self { object: object.clone().unbind(), dim })
+    }
+}
+
+impl Embedder for PyObjectEmbedder {
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn embed(&self, text: &str) -> ::imesde::Result<Vec<f32>> {
+        Python::with_gil(|py| {
+            self.object.call_method1(py, "embed", (text,))?.extract::<Vec<f32>>(py)
+        }).map_err(|e| Error::Embedder(e.to_string()))
+    }
+}
+
+// Batching is enabled by setting either option; the other keeps its default.
+fn batch_config(max_batch_size: Option<usize>, max_batch_wait_ms: Option<f64>) -> PyResult<Option<BatchConfig>> {
+    if max_batch_size.is_none() && max_batch_wait_ms.is_none() {
+        return Ok(None);
+    }
+    let mut config = BatchConfig::default();
+    if let Some(size) = max_batch_size {
+        config.max_batch_size = size;
+    }
+    if let Some(ms) = max_batch_wait_ms {
+        if !ms.is_finite() || ms < 0.0 {
+            return Err(PyValueError::new_err("max_batch_wait_ms must be a non-negative number"));
+        }
+        config.max_wait = to_duration(ms / 1000.0, "max_batch_wait_ms")?;
+    }
+    Ok(Some(config))
+}
+
+// Rejects durations too long for `Duration` as a config error instead of panicking.
+fn to_duration(secs: f64, name: &str) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(secs)
+        .map_err(|_| to_py_err(Error::InvalidConfig(format!("{} is out of range: {}", name, secs))))
+}
+
+// The vector store settings shared by every constructor.
+struct BufferOptions {
+    num_shards: usize,
+    shard_size: usize,
+    max_age: Option<Duration>,
+    truncate_dim: Option<usize>,
+    storage: VectorStorage,
+    metric: Metric,
+}
+
+impl BufferOptions {
+    fn parse(
+        num_shards: Option<usize>,
+        shard_size: Option<usize>,
+        max_age_secs: Option<f64>,
+        truncate_dim: Option<usize>,
+        storage: Option<&str>,
+        rerank: Option<usize>,
+        metric: Option<&str>,
+    ) -> PyResult<Self> {
+        let max_age = match max_age_secs {
+            Some(secs) if !secs.is_finite() || secs <= 0.0 => {
+                return Err(PyValueError::new_err("max_age_secs must be a positive number"));
+            }
+            secs => secs.map(|secs| to_duration(secs, "max_age_secs")).transpose()?,
+        };
+        Ok(Self {
+            num_shards: num_shards.unwrap_or(DEFAULT_NUM_SHARDS),
+            shard_size: shard_size.unwrap_or(DEFAULT_SHARD_SIZE),
+            max_age,
+            truncate_dim,
+            storage: vector_storage(storage, rerank)?,
+            metric: metric.map(str::parse::<Metric>).transpose().map_err(to_py_err)?.unwrap_or_default(),
+        })
+    }
+
+    fn build(self, dim: usize) -> PyResult<Arc<ShardedCircularBuffer>> {
+        let mut buffer = ShardedCircularBuffer::new(self.num_shards, self.shard_size)
+            .and_then(|buffer| buffer.with_dim(dim))
+            .and_then(|buffer| buffer.with_storage(self.storage))
+            .and_then(|buffer| buffer.with_metric(self.metric))
+            .map_err(to_py_err)?;
+        if let Some(max_age) = self.max_age {
+            buffer = buffer.with_max_age(max_age);
+        }
+        if let Some(truncate_dim) = self.truncate_dim {
+            buffer = buffer.with_truncate_dim(truncate_dim).map_err(to_py_err)?;
+        }
+        let buffer = Arc::new(buffer);
+        if let Some(max_age) = buffer.max_age() {
+            // Sweep a few times per window so a quiet stream still drops expired records.
+            buffer.spawn_sweeper((max_age / 4).max(Duration::from_secs(1)));
+        }
+        Ok(buffer)
+    }
+}
+
+// `rerank` is the oversampling factor of quantized storage.
+fn vector_storage(storage: Option<&str>, rerank: Option<usize>) -> PyResult<VectorStorage> {
+    if rerank == Some(0) {
+        return Err(PyValueError::new_err("rerank must be a positive oversampling factor"));
+    }
+    match storage.unwrap_or("f32") {
+        "f32" if rerank.is_some() => Err(PyValueError::new_err("rerank requires quantized storage")),
+        "f32" => Ok(VectorStorage::F32),
+        "int8" => Ok(VectorStorage::Int8 { rerank }),
+        "binary" => Ok(VectorStorage::Binary { oversample: rerank.unwrap_or(DEFAULT_OVERSAMPLE) }),
+        other => Err(PyValueError::new_err(format!(
+            "Unknown storage '{}'. Use \"f32\", \"int8\" or \"binary\".", other
+        ))),
+    }
+}
+
+fn new_record(id: String, vector: Vec<f32>, text: String, timestamp: Option<u64>) -> VectorRecord {
+    match timestamp {
+        Some(ts) => VectorRecord::new_with_event_time(id, vector, text, ts),
+        None => VectorRecord::new(id, vector, text),
+    }
+}
+
+// Python timestamps are Unix seconds, as returned by `time.time()` or `datetime.timestamp()`.
+fn to_timestamp(secs: f64) -> PyResult<u64> {
+    timestamp_from_secs_f64(secs)
+        .ok_or_else(|| PyValueError::new_err("timestamps must be non-negative Unix timestamps in seconds"))
+}
+
+fn to_timestamp_list(timestamps: Option<Vec<f64>>, len: usize) -> PyResult<Vec<Option<u64>>> {
+    match timestamps {
+        Some(timestamps) => {
+            if timestamps.len() != len {
+                return Err(PyValueError::new_err("Timestamps and texts must have the same length"));
+            }
+            timestamps.into_iter().map(|secs| to_timestamp(secs).map(Some)).collect()
+        }
+        None => Ok(vec![None; len]),
+    }
+}
+
+const DEFAULT_RECENCY_WEIGHT: f32 = 0.5;
+#[cfg(feature = "embedder")]
+const DEFAULT_CHUNK_OVERLAP: usize = 32;
+
+fn search_options(
+    filter: Option<&Filter>,
+    since: Option<f64>,
+    until: Option<f64>,
+    half_life_secs: Option<f64>,
+    recency_weight: Option<f32>,
+) -> PyResult<SearchOptions<'_>> {
+    let scoring = match half_life_secs {
+        Some(secs) if secs.is_finite() && secs > 0.0 => ScoringMode::recency_weighted(
+            to_duration(secs, "half_life_secs")?,
+            recency_weight.unwrap_or(DEFAULT_RECENCY_WEIGHT),
+        ),
+        Some(_) => return Err(PyValueError::new_err("half_life_secs must be a positive number")),
+        None if recency_weight.is_some() => {
+            return Err(PyValueError::new_err("recency_weight requires half_life_secs"));
+        }
+        None => ScoringMode::Similarity,
+    };
+    Ok(SearchOptions {
+        filter,
+        since: since.map(to_timestamp).transpose()?,
+        until: until.map(to_timestamp).transpose()?,
+        min_score: None,
+        scoring,
+        with_vectors: false,
+    })
+}
+
+fn to_attribute_value(value: &Bound<'_, PyAny>) -> PyResult<AttributeValue> {
+    // bool must be checked before int: in Python, True is an int.
+    if value.is_instance_of::<PyBool>() {
+        Ok(AttributeValue::Bool(value.extract()?))
+    } else if value.is_instance_of::<PyInt>() {
+        Ok(AttributeValue::Int(value.extract()?))
+    } else if value.is_instance_of::<PyFloat>() {
+        Ok(AttributeValue::Float(value.extract()?))
+    } else if value.is_instance_of::<PyString>() {
+        Ok(AttributeValue::Str(value.extract()?))
+    } else if value.hasattr("timestamp")? {
+        // datetime-like objects
+        let secs: f64 = value.call_method0("timestamp")?.extract()?;
+        let time = Duration::try_from_secs_f64(secs)
+            .ok()
+            .and_then(|offset| UNIX_EPOCH.checked_add(offset))
+            .ok_or_else(|| PyValueError::new_err("timestamps before 1970 are not supported"))?;
+        Ok(AttributeValue::Timestamp(time))
+    } else {
+        Err(PyValueError::new_err(format!(
+            "unsupported attribute value {}; expected str, int, float, bool or datetime",
+            value.repr()?
+        )))
+    }
+}
+
+fn to_attributes(dict: Option<&Bound<'_, PyDict>>) -> PyResult<Attributes> {
+    let mut attributes = Attributes::new();
+    if let Some(dict) = dict {
+        for (key, value) in dict.iter() {
+            attributes.insert(key.extract()?, to_attribute_value(&value)?);
+        }
+    }
+    Ok(attributes)
+}
+
+fn to_attributes_list(dicts: Option<Vec<Bound<'_, PyDict>>>, len: usize) -> PyResult<Vec<Attributes>> {
+    match dicts {
+        Some(dicts) => {
+            if dicts.len() != len {
+                return Err(PyValueError::new_err("Attributes and texts must have the same length"));
+            }
+            dicts.iter().map(|dict| to_attributes(Some(dict))).collect()
+        }
+        None => Ok(vec![Attributes::new(); len]),
+    }
+}
+
+// Filters use a MongoDB-like dict syntax:
+//   {"host": "db-1", "severity": {"$gte": 3}}       implicit AND of field clauses
+//   {"$or": [{...}, {...}]}, {"$and": [...]}, {"$not": {...}}
+//   field operators: $eq, $ne, $gt, $gte, $lt, $lte, $in, $exists
+fn to_filter(dict: &Bound<'_, PyDict>) -> PyResult<Filter> {
+    let mut clauses = Vec::with_capacity(dict.len());
+    for (key, value) in dict.iter() {
+        let key: String = key.extract()?;
+        let clause = match key.as_str() {
+            "$and" => Filter::And(to_filter_list(&value)?),
+            "$or" => Filter::Or(to_filter_list(&value)?),
+            "$not" => Filter::Not(Box::new(to_filter(value.downcast::<PyDict>()?)?)),
+            _ => to_field_filter(key, &value)?,
+        };
+        clauses.push(clause);
+    }
+    Ok(match clauses.len() {
+        1 => clauses.remove(0),
+        _ => Filter::And(clauses),
+    })
+}
+
+fn to_filter_list(value: &Bound<'_, PyAny>) -> PyResult<Vec<Filter>> {
+    value
+        .downcast::<PyList>()?
+        .iter()
+        .map(|item| to_filter(item.downcast::<PyDict>()?))
+        .collect()
+}
+
+fn to_field_filter(key: String, value: &Bound<'_, PyAny>) -> PyResult<Filter> {
+    let Ok(ops) = value.downcast::<PyDict>() else {
+        return Ok(Filter::Eq(key, to_attribute_value(value)?));
+    };
+
+    let mut clauses = Vec::with_capacity(ops.len());
+    for (op, operand) in ops.iter() {
+        let op: String = op.extract()?;
+        let key = key.clone();
+        let clause = match op.as_str() {
+            "$eq" => Filter::Eq(key, to_attribute_value(&operand)?),
+            "$ne" => Filter::Ne(key, to_attribute_value(&operand)?),
+            "$gt" => Filter::Gt(key, to_attribute_value(&operand)?),
+            "$gte" => Filter::Gte(key, to_attribute_value(&operand)?),
+            "$lt" => Filter::Lt(key, to_attribute_value(&operand)?),
+            "$lte" => Filter::Lte(key, to_attribute_value(&operand)?),
+            "$in" => Filter::In(
+                key,
+                operand.try_iter()?.map(|item| to_attribute_value(&item?)).collect::<PyResult<_>>()?,
+            ),
+            "$exists" => {
+                let exists = Filter::Exists(key);
+                if operand.extract::<bool>()? { exists } else { exists.not() }
+            }
+            _ => return Err(PyValueError::new_err(format!("unknown filter operator '{}'", op))),
+        };
+        clauses.push(clause);
+    }
+    Ok(match clauses.len() {
+        1 => clauses.remove(0),
+        _ => Filter::And(clauses),
+    })
+}
+
 #[pymodule]
 fn imesde(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<PyImesde>()?;
+    m.add_class::<PyHashEmbedder>()?;
+    m.add("ImesdeError", m.py().get_type::<ImesdeError>())?;
+    m.add("ModelLoadError", m.py().get_type::<ModelLoadError>())?;
+    m.add("EmbeddingError", m.py().get_type::<EmbeddingError>())?;
+    m.add("ConfigError", m.py().get_type::<ConfigError>())?;
+    m.add("VectorError", m.py().get_type::<VectorError>())?;
     Ok(())
 }
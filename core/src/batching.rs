@@ -0,0 +1,305 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::embedding::{Chunk, Embedder};
+use crate::error::{Error, Result};
+
+/// How concurrent single-text requests are grouped into batches.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    /// Largest batch handed to the embedder.
+    pub max_batch_size: usize,
+    /// How long the first request of a batch waits for others to join it.
+    pub max_wait: Duration,
+    /// Batches embedded concurrently. Match it to the embedder's session count.
+    pub workers: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_size: 32,
+            max_wait: Duration::from_millis(2),
+            workers: 2,
+        }
+    }
+}
+
+enum Request {
+    Embed(String, Sender<Result<Vec<f32>>>),
+    Document(String, Sender<Result<Vec<f32>>>),
+    Documents(String, Sender<Result<Vec<Chunk>>>),
+}
+
+/// A result that a batching worker is still computing.
+pub struct Pending<T> {
+    receiver: Receiver<Result<T>>,
+}
+
+impl<T> Pending<T> {
+    /// Blocks until the batch containing this request has been embedded.
+    pub fn wait(self) -> Result<T> {
+        self.receiver.recv()
+            .unwrap_or_else(|_| Err(Error::Embedder("batching worker stopped".to_string())))
+    }
+}
+
+/// Wraps an embedder so that concurrent single-text `embed`, `embed_document` and
+/// `embed_documents` calls are collected into one `embed_batch`, `embed_document_batch`
+/// or `embed_documents` call, amortizing inference across callers. Calls that already
+/// pass several texts, and queries, go straight to the inner embedder.
+pub struct BatchingEmbedder {
+    inner: Arc<dyn Embedder>,
+    sender: Option<Sender<Request>>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl BatchingEmbedder {
+    pub fn new(inner: Arc<dyn Embedder>, config: BatchConfig) -> Result<Self> {
+        if config.max_batch_size == 0 || config.workers == 0 {
+            return Err(Error::InvalidConfig("max_batch_size and workers must be positive".to_string()));
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        // Workers take turns collecting a batch; the others embed theirs meanwhile.
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..config.workers)
+            .map(|_| {
+                let inner = Arc::clone(&inner);
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || {
+                    while let Some(batch) = collect_batch(&receiver, &config) {
+                        embed_batch(inner.as_ref(), batch);
+                    }
+                })
+            })
+            .collect();
+
+        Ok(Self { inner, sender: Some(sender), workers })
+    }
+
+    /// Queues `text` for embedding without waiting for the result.
+    pub fn submit(&self, text: String) -> Pending<Vec<f32>> {
+        let (reply, receiver) = mpsc::channel();
+        self.send(Request::Embed(text, reply));
+        Pending { receiver }
+    }
+
+    // Queues `text` for embedding as a single stored vector.
+    fn submit_document_vector(&self, text: String) -> Pending<Vec<f32>> {
+        let (reply, receiver) = mpsc::channel();
+        self.send(Request::Document(text, reply));
+        Pending { receiver }
+    }
+
+    /// Queues `text` for embedding as a document without waiting for the result.
+    pub fn submit_document(&self, text: String) -> Pending<Vec<Chunk>> {
+        let (reply, receiver) = mpsc::channel();
+        self.send(Request::Documents(text, reply));
+        Pending { receiver }
+    }
+
+    // A failed send drops the reply sender, which `Pending::wait` reports as an error.
+    fn send(&self, request: Request) {
+        if let Some(sender) = &self.sender {
+            sender.send(request).ok();
+        }
+    }
+}
+
+impl Drop for BatchingEmbedder {
+    fn drop(&mut self) {
+        // Closing the channel lets the workers finish the queued requests and exit.
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            worker.join().ok();
+        }
+    }
+}
+
+impl Embedder for BatchingEmbedder {
+    fn dim(&self) -> usize {
+        self.inner.dim()
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        self.submit(text.to_string()).wait()
+    }
+
+    fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        self.inner.embed_batch(texts)
+    }
+
+    fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        self.inner.embed_query(query)
+    }
+
+    fn embed_document(&self, text: &str) -> Result<Vec<f32>> {
+        self.submit_document_vector(text.to_string()).wait()
+    }
+
+    fn embed_document_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        self.inner.embed_document_batch(texts)
+    }
+
+    fn embed_documents(&self, mut texts: Vec<String>) -> Result<Vec<Vec<Chunk>>> {
+        match texts.len() {
+            1 => Ok(vec![self.submit_document(texts.remove(0)).wait()?]),
+            _ => self.inner.embed_documents(texts),
+        }
+    }
+}
+
+// Blocks for the first request, then gathers more until the batch is full or `max_wait`
+// has passed. Returns `None` once the embedder has been dropped and the queue is drained.
+fn collect_batch(receiver: &Mutex<Receiver<Request>>, config: &BatchConfig) -> Option<Vec<Request>> {
+    let receiver = receiver.lock().ok()?;
+    let first = receiver.recv().ok()?;
+    let deadline = Instant::now() + config.max_wait;
+
+    let mut batch = vec![first];
+    while batch.len() < config.max_batch_size {
+        match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(request) => batch.push(request),
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    Some(batch)
+}
+
+fn embed_batch(embedder: &dyn Embedder, batch: Vec<Request>) {
+    let mut embeds = Vec::new();
+    let mut document_vectors = Vec::new();
+    let mut documents = Vec::new();
+    for request in batch {
+        match request {
+            Request::Embed(text, reply) => embeds.push((text, reply)),
+            Request::Document(text, reply) => document_vectors.push((text, reply)),
+            Request::Documents(text, reply) => documents.push((text, reply)),
+        }
+    }
+    reply_all(embeds, |texts| embedder.embed_batch(texts));
+    reply_all(document_vectors, |texts| embedder.embed_document_batch(texts));
+    reply_all(documents, |texts| embedder.embed_documents(texts));
+}
+
+// Runs one batch call for all requests. If it fails, every request is retried on its own
+// so that one bad input only fails its own caller.
+fn reply_all<T>(requests: Vec<(String, Sender<Result<T>>)>, embed: impl Fn(Vec<String>) -> Result<Vec<T>>) {
+    if requests.is_empty() {
+        return;
+    }
+    let texts = requests.iter().map(|(text, _)| text.clone()).collect();
+    match embed(texts) {
+        Ok(results) if results.len() == requests.len() => {
+            for ((_, reply), result) in requests.into_iter().zip(results) {
+                reply.send(Ok(result)).ok();
+            }
+        }
+        _ => {
+            for (text, reply) in requests {
+                let result = embed(vec![text]).and_then(|mut results| {
+                    results.pop().ok_or_else(|| Error::Embedder("embedder returned no result".to_string()))
+                });
+                reply.send(result).ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedding::HashEmbedder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Counts the batch calls reaching the inner embedder and rejects empty texts.
+    struct CountingEmbedder {
+        inner: HashEmbedder,
+        batch_calls: AtomicUsize,
+    }
+
+    impl Embedder for CountingEmbedder {
+        fn dim(&self) -> usize {
+            self.inner.dim()
+        }
+
+        fn embed(&self, text: &str) -> Result<Vec<f32>> {
+            if text.is_empty() {
+                return Err(Error::Embedder("empty text".to_string()));
+            }
+            self.inner.embed(text)
+        }
+
+        fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+            self.batch_calls.fetch_add(1, Ordering::SeqCst);
+            texts.iter().map(|text| self.embed(text)).collect()
+        }
+
+        fn embed_document_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+            self.embed_batch(texts)
+        }
+    }
+
+    #[test]
+    fn test_concurrent_requests_are_batched() {
+        let inner = Arc::new(CountingEmbedder { inner: HashEmbedder::new(32).unwrap(), batch_calls: AtomicUsize::new(0) });
+        let config = BatchConfig { max_batch_size: 64, max_wait: Duration::from_millis(200), workers: 1 };
+        let batching = BatchingEmbedder::new(inner.clone(), config).unwrap();
+
+        let texts: Vec<String> = (0..16).map(|i| format!("log line {}", i)).collect();
+        let pending: Vec<_> = texts.iter().map(|text| batching.submit(text.clone())).collect();
+        for (text, pending) in texts.iter().zip(pending) {
+            assert_eq!(pending.wait().unwrap(), inner.inner.embed(text).unwrap());
+        }
+        assert_eq!(inner.batch_calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_concurrent_document_embeds_are_batched() {
+        let inner = Arc::new(CountingEmbedder { inner: HashEmbedder::new(32).unwrap(), batch_calls: AtomicUsize::new(0) });
+        let config = BatchConfig { max_batch_size: 64, max_wait: Duration::from_millis(200), workers: 1 };
+        let batching = Arc::new(BatchingEmbedder::new(inner.clone(), config).unwrap());
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let batching = Arc::clone(&batching);
+                thread::spawn(move || batching.embed_document(&format!("log line {}", i)).unwrap())
+            })
+            .collect();
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().unwrap(), inner.inner.embed(&format!("log line {}", i)).unwrap());
+        }
+        assert_eq!(inner.batch_calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_cache_hits_skip_the_batch_queue() {
+        use crate::cache::{CachingEmbedder, EmbeddingCache};
+
+        let inner = Arc::new(CountingEmbedder { inner: HashEmbedder::new(32).unwrap(), batch_calls: AtomicUsize::new(0) });
+        let config = BatchConfig { max_batch_size: 64, max_wait: Duration::from_millis(200), workers: 1 };
+        let batching = Arc::new(BatchingEmbedder::new(inner.clone(), config).unwrap());
+        let cached = CachingEmbedder::new(batching, Arc::new(EmbeddingCache::new(8)));
+
+        let miss = cached.embed("disk full").unwrap();
+        let start = Instant::now();
+        assert_eq!(cached.embed("disk full").unwrap(), miss);
+        assert!(start.elapsed() < config.max_wait, "cache hit waited {:?}", start.elapsed());
+        assert_eq!(inner.batch_calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_failed_request_does_not_fail_its_batch() {
+        let inner = Arc::new(CountingEmbedder { inner: HashEmbedder::new(32).unwrap(), batch_calls: AtomicUsize::new(0) });
+        let config = BatchConfig { max_batch_size: 64, max_wait: Duration::from_millis(200), workers: 1 };
+        let batching = BatchingEmbedder::new(inner, config).unwrap();
+
+        let good = batching.submit("disk full".to_string());
+        let bad = batching.submit(String::new());
+        assert!(good.wait().is_ok());
+        assert!(bad.wait().is_err());
+    }
+}
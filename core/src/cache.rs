@@ -0,0 +1,327 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::embedding::{Chunk, Embedder};
+use crate::error::{Error, Result};
+
+/// Entries kept when the cache size is not configured.
+pub const DEFAULT_CACHE_SIZE: usize = 10_000;
+
+/// Hit/miss counters and occupancy of an [`EmbeddingCache`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+// The same text embeds differently as a query or a document when prefixes are configured.
+// `Documents` holds single-chunk results of `embed_documents`, which differ from
+// `embed_document` when chunking splits long texts into several records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Plain,
+    Query,
+    Document,
+    Documents,
+}
+
+// The kind and the whitespace-normalized text. The full text is compared, so distinct
+// texts never share an entry.
+type Key = (Kind, String);
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<Key, (Vec<f32>, u64)>,
+    // Last-use tick to key, oldest first.
+    recency: BTreeMap<u64, Key>,
+    tick: u64,
+}
+
+/// A bounded LRU cache of embeddings, keyed by the whitespace-normalized text.
+pub struct EmbeddingCache {
+    lru: Mutex<Lru>,
+    capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl EmbeddingCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lru: Mutex::new(Lru::default()),
+            capacity,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            len: self.lru.lock().expect("cache poisoned").entries.len(),
+            capacity: self.capacity,
+        }
+    }
+
+    fn get(&self, key: &Key) -> Option<Vec<f32>> {
+        let mut lru = self.lru.lock().expect("cache poisoned");
+        lru.tick += 1;
+        let tick = lru.tick;
+        let found = match lru.entries.get_mut(key) {
+            Some((vector, last_used)) => {
+                let previous = std::mem::replace(last_used, tick);
+                Some((vector.clone(), previous))
+            }
+            None => None,
+        };
+        match found {
+            Some((vector, previous)) => {
+                lru.recency.remove(&previous);
+                lru.recency.insert(tick, key.clone());
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(vector)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    fn insert(&self, key: Key, vector: Vec<f32>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut lru = self.lru.lock().expect("cache poisoned");
+        lru.tick += 1;
+        let tick = lru.tick;
+        if let Some((_, previous)) = lru.entries.insert(key.clone(), (vector, tick)) {
+            lru.recency.remove(&previous);
+        }
+        lru.recency.insert(tick, key);
+        while lru.entries.len() > self.capacity {
+            let Some((_, oldest)) = lru.recency.pop_first() else { break };
+            lru.entries.remove(&oldest);
+        }
+    }
+}
+
+/// Wraps an embedder with an [`EmbeddingCache`], so repeated texts skip inference.
+/// Documents that are split into several chunks are not cached.
+pub struct CachingEmbedder {
+    inner: Arc<dyn Embedder>,
+    cache: Arc<EmbeddingCache>,
+}
+
+impl CachingEmbedder {
+    pub fn new(inner: Arc<dyn Embedder>, cache: Arc<EmbeddingCache>) -> Self {
+        Self { inner, cache }
+    }
+
+    pub fn cache(&self) -> &Arc<EmbeddingCache> {
+        &self.cache
+    }
+
+    fn cached(&self, kind: Kind, text: &str, embed: impl FnOnce(&str) -> Result<Vec<f32>>) -> Result<Vec<f32>> {
+        let key = cache_key(kind, text);
+        if let Some(vector) = self.cache.get(&key) {
+            return Ok(vector);
+        }
+        let vector = embed(text)?;
+        self.cache.insert(key, vector.clone());
+        Ok(vector)
+    }
+
+    // Embeds only the texts missing from the cache, in one call.
+    fn cached_batch(
+        &self,
+        kind: Kind,
+        texts: Vec<String>,
+        embed: impl FnOnce(Vec<String>) -> Result<Vec<Vec<f32>>>,
+    ) -> Result<Vec<Vec<f32>>> {
+        let keys: Vec<Key> = texts.iter().map(|text| cache_key(kind, text)).collect();
+        let mut vectors: Vec<Option<Vec<f32>>> = keys.iter().map(|key| self.cache.get(key)).collect();
+
+        let missing: Vec<usize> = (0..texts.len()).filter(|&i| vectors[i].is_none()).collect();
+        if !missing.is_empty() {
+            let embedded = embed(missing.iter().map(|&i| texts[i].clone()).collect())?;
+            check_len(&embedded, missing.len())?;
+            for (i, vector) in missing.into_iter().zip(embedded) {
+                self.cache.insert(keys[i].clone(), vector.clone());
+                vectors[i] = Some(vector);
+            }
+        }
+        Ok(vectors.into_iter().flatten().collect())
+    }
+}
+
+impl Embedder for CachingEmbedder {
+    fn dim(&self) -> usize {
+        self.inner.dim()
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        self.cached(Kind::Plain, text, |text| self.inner.embed(text))
+    }
+
+    fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        self.cached_batch(Kind::Plain, texts, |texts| self.inner.embed_batch(texts))
+    }
+
+    fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        self.cached(Kind::Query, query, |query| self.inner.embed_query(query))
+    }
+
+    fn embed_document(&self, text: &str) -> Result<Vec<f32>> {
+        self.cached(Kind::Document, text, |text| self.inner.embed_document(text))
+    }
+
+    fn embed_document_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        self.cached_batch(Kind::Document, texts, |texts| self.inner.embed_document_batch(texts))
+    }
+
+    fn embed_documents(&self, texts: Vec<String>) -> Result<Vec<Vec<Chunk>>> {
+        let keys: Vec<Key> = texts.iter().map(|text| cache_key(Kind::Documents, text)).collect();
+        let mut documents: Vec<Option<Vec<Chunk>>> = texts.iter()
+            .zip(&keys)
+            .map(|(text, key)| {
+                self.cache.get(key).map(|vector| vec![Chunk { text: text.clone(), vector }])
+            })
+            .collect();
+
+        let missing: Vec<usize> = (0..texts.len()).filter(|&i| documents[i].is_none()).collect();
+        if !missing.is_empty() {
+            let embedded = self.inner.embed_documents(missing.iter().map(|&i| texts[i].clone()).collect())?;
+            check_len(&embedded, missing.len())?;
+            for (i, chunks) in missing.into_iter().zip(embedded) {
+                if let [chunk] = chunks.as_slice() {
+                    self.cache.insert(keys[i].clone(), chunk.vector.clone());
+                }
+                documents[i] = Some(chunks);
+            }
+        }
+        Ok(documents.into_iter().flatten().collect())
+    }
+}
+
+// Every missing text must get its own result, or the batch would come back short.
+fn check_len<T>(results: &[T], expected: usize) -> Result<()> {
+    if results.len() != expected {
+        return Err(Error::Embedder(format!(
+            "embedder returned {} results for {} texts",
+            results.len(),
+            expected
+        )));
+    }
+    Ok(())
+}
+
+// Texts differing only in surrounding or repeated whitespace share an entry.
+fn cache_key(kind: Kind, text: &str) -> Key {
+    let normalized: Vec<&str> = text.split_whitespace().collect();
+    (kind, normalized.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedding::HashEmbedder;
+
+    #[test]
+    fn test_cache_hits_and_eviction() {
+        let cache = Arc::new(EmbeddingCache::new(2));
+        let embedder = CachingEmbedder::new(Arc::new(HashEmbedder::new(16).unwrap()), Arc::clone(&cache));
+
+        let reset = embedder.embed("Connection reset").unwrap();
+        embedder.embed("health check OK").unwrap();
+        assert_eq!(embedder.embed("  Connection   reset ").unwrap(), reset);
+        // A query is a different entry than the same plain text. Adding it evicts
+        // "health check OK", the least recently used entry.
+        embedder.embed_query("Connection reset").unwrap();
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.len), (1, 3, 2));
+        embedder.embed("Connection reset").unwrap();
+        embedder.embed("health check OK").unwrap();
+        assert_eq!((cache.stats().hits, cache.stats().misses), (2, 4));
+    }
+
+    #[test]
+    fn test_cached_batch_keeps_order() {
+        let inner = HashEmbedder::new(16).unwrap();
+        let cache = Arc::new(EmbeddingCache::new(8));
+        let embedder = CachingEmbedder::new(Arc::new(inner.clone()), Arc::clone(&cache));
+
+        embedder.embed("b").unwrap();
+        let texts: Vec<String> = ["a", "b", "c"].iter().map(|t| t.to_string()).collect();
+        let vectors = embedder.embed_batch(texts.clone()).unwrap();
+        assert_eq!(vectors, inner.embed_batch(texts).unwrap());
+        assert_eq!(cache.stats().hits, 1);
+    }
+
+    // Drops the last text of every batch.
+    struct ShortEmbedder(HashEmbedder);
+
+    impl Embedder for ShortEmbedder {
+        fn dim(&self) -> usize {
+            self.0.dim()
+        }
+
+        fn embed(&self, text: &str) -> Result<Vec<f32>> {
+            self.0.embed(text)
+        }
+
+        fn embed_batch(&self, mut texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+            texts.pop();
+            self.0.embed_batch(texts)
+        }
+    }
+
+    // Splits every document into one chunk per word, like chunking in records mode.
+    struct WordChunker(HashEmbedder);
+
+    impl Embedder for WordChunker {
+        fn dim(&self) -> usize {
+            self.0.dim()
+        }
+
+        fn embed(&self, text: &str) -> Result<Vec<f32>> {
+            self.0.embed(text)
+        }
+
+        fn embed_documents(&self, texts: Vec<String>) -> Result<Vec<Vec<Chunk>>> {
+            texts.iter()
+                .map(|text| {
+                    text.split_whitespace()
+                        .map(|word| Ok(Chunk { text: word.to_string(), vector: self.0.embed(word)? }))
+                        .collect()
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_single_vector_entry_does_not_hide_chunks() {
+        let inner = WordChunker(HashEmbedder::new(16).unwrap());
+        let embedder = CachingEmbedder::new(Arc::new(WordChunker(HashEmbedder::new(16).unwrap())), Arc::new(EmbeddingCache::new(8)));
+        let text = "disk full on /var".to_string();
+
+        assert_eq!(embedder.embed_document(&text).unwrap(), inner.embed_document(&text).unwrap());
+        let chunks = embedder.embed_documents(vec![text.clone()]).unwrap().remove(0);
+        let expected = inner.embed_documents(vec![text]).unwrap().remove(0);
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks.iter().zip(&expected).all(|(a, b)| a.text == b.text && a.vector == b.vector));
+    }
+
+    #[test]
+    fn test_short_batch_is_an_error() {
+        let embedder = CachingEmbedder::new(Arc::new(ShortEmbedder(HashEmbedder::new(16).unwrap())), Arc::new(EmbeddingCache::new(8)));
+        let texts: Vec<String> = ["a", "b"].iter().map(|t| t.to_string()).collect();
+        assert!(matches!(embedder.embed_batch(texts.clone()), Err(Error::Embedder(_))));
+        assert!(matches!(embedder.embed_documents(texts), Err(Error::Embedder(_))));
+    }
+}
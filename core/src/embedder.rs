@@ -1,104 +1,526 @@
-use tokenizers::Tokenizer;
-use ndarray::{Array2, Axis, ArrayViewD, IxDyn, s};
-use ort::session::Session;
+use tokenizers::{Encoding, PostProcessor, Tokenizer, TruncationDirection};
+use ndarray::{Array2, ArrayView2, Axis, ArrayViewD, IxDyn, s};
+use ort::session::{Session, SessionInputValue};
 use ort::value::Value;
 use ort::session::builder::GraphOptimizationLevel;
-use crossbeam_queue::ArrayQueue;
-use std::sync::Arc;
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
+use std::sync::{Condvar, Mutex};
+use crate::embedding::{Chunk, Embedder};
+use crate::error::{Error, Result};
+
+// Output tensors we know how to use, in order of preference when none is configured.
+// `sentence_embedding` is what sentence-transformers exports emit after their own pooling.
+const KNOWN_OUTPUTS: [&str; 3] = ["sentence_embedding", "last_hidden_state", "token_embeddings"];
+const TOKEN_OUTPUTS: [&str; 2] = ["last_hidden_state", "token_embeddings"];
+
+/// Sessions in the pool when the config sets none.
+pub const DEFAULT_NUM_SESSIONS: usize = 2;
+
+/// Max sequence length used when neither the config nor the tokenizer sets one.
+pub const DEFAULT_MAX_LENGTH: usize = 512;
+// Anything shorter leaves no room for content next to the special tokens.
+const MIN_MAX_LENGTH: usize = 8;
+// Most inputs run in one model call per length bucket.
+const MAX_BUCKET_SIZE: usize = 64;
+// Inputs up to this many tokens share a bucket regardless of their relative length.
+const MIN_BUCKET_LENGTH: usize = 16;
+
+/// How token embeddings are reduced to a single vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pooling {
+    /// The first token (`[CLS]`). What BGE models are trained for.
+    Cls,
+    /// Mean over the non-padding tokens.
+    Mean,
+    /// Element-wise max over the non-padding tokens.
+    Max,
+    /// The last non-padding token, for decoder-style embedders.
+    LastToken,
+    /// The output is already one vector per input, e.g. `sentence_embedding`.
+    None,
+}
+
+impl FromStr for Pooling {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "cls" => Ok(Pooling::Cls),
+            "mean" => Ok(Pooling::Mean),
+            "max" => Ok(Pooling::Max),
+            "last" | "last_token" => Ok(Pooling::LastToken),
+            "none" => Ok(Pooling::None),
+            _ => Err(Error::InvalidConfig(format!(
+                "unknown pooling '{}' (expected cls, mean, max, last_token or none)", s
+            ))),
+        }
+    }
+}
+
+impl Pooling {
+    // Reduces one item's `[seq_len, dim]` token embeddings, of which the first `len` are real tokens.
+    fn apply(self, tokens: ArrayView2<f32>, len: usize) -> Result<Vec<f32>> {
+        if len == 0 {
+            return Err(Error::InvalidVector("empty token sequence".to_string()));
+        }
+        let tokens = tokens.slice(s![0..len, ..]);
+        let vector = match self {
+            Pooling::Cls => tokens.row(0).to_vec(),
+            Pooling::LastToken => tokens.row(len - 1).to_vec(),
+            Pooling::Mean => tokens.mean_axis(Axis(0))
+                .ok_or_else(|| Error::InvalidVector("empty token sequence".to_string()))?
+                .to_vec(),
+            Pooling::Max => tokens.fold_axis(Axis(0), f32::NEG_INFINITY, |&acc, &x| acc.max(x)).to_vec(),
+            Pooling::None => {
+                return Err(Error::InvalidConfig(
+                    "pooling 'none' needs a pre-pooled output, got token embeddings".to_string(),
+                ));
+            }
+        };
+        Ok(vector)
+    }
+}
+
+/// What happens to the token windows of a text longer than the max sequence length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkMode {
+    /// Every window is stored as its own record, linked to the parent document's id.
+    Records,
+    /// The window vectors are averaged into one.
+    Average,
+}
+
+impl FromStr for ChunkMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "records" => Ok(ChunkMode::Records),
+            "average" => Ok(ChunkMode::Average),
+            _ => Err(Error::InvalidConfig(format!(
+                "unknown chunk mode '{}' (expected records or average)", s
+            ))),
+        }
+    }
+}
+
+/// Splits long inputs into overlapping token windows instead of truncating them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunking {
+    /// Number of tokens shared by consecutive windows.
+    pub overlap: usize,
+    pub mode: ChunkMode,
+}
+
+/// Overrides for how the embedder loads and talks to the ONNX graph. Every field left
+/// as `None` is detected from the model or falls back to a default.
+#[derive(Debug, Clone, Default)]
+pub struct ModelConfig {
+    /// Feed `token_type_ids` to the model. Auto-detected when `None`.
+    pub token_type_ids: Option<bool>,
+    /// Name of the output tensor holding the embeddings. Auto-detected when `None`.
+    pub output_name: Option<String>,
+    /// Pooling applied to token embeddings. When `None`, pre-pooled outputs are used
+    /// as-is and token embeddings are mean-pooled.
+    pub pooling: Option<Pooling>,
+    /// Longest token sequence fed to the model. Falls back to the tokenizer's own
+    /// truncation setting, then [`DEFAULT_MAX_LENGTH`].
+    pub max_length: Option<usize>,
+    /// Chunk inputs longer than `max_length` instead of truncating them.
+    pub chunking: Option<Chunking>,
+    /// Template applied to queries by [`TextEmbedder::embed_query`], e.g. `"query: "`.
+    /// A `{text}` placeholder marks where the text goes; otherwise the template is a prefix.
+    pub query_prefix: Option<String>,
+    /// Template applied to stored texts by the `embed_document*` methods, e.g. `"passage: "`.
+    pub document_prefix: Option<String>,
+    /// Sessions that can run inference concurrently. Defaults to [`DEFAULT_NUM_SESSIONS`].
+    pub num_sessions: Option<usize>,
+    /// Threads each session uses within an operator. Defaults to the available cores
+    /// divided between the sessions, so the pool never oversubscribes the CPU.
+    pub intra_threads: Option<usize>,
+    /// Threads each session uses to run independent operators in parallel. ONNX Runtime's default when `None`.
+    pub inter_threads: Option<usize>,
+}
+
+// A prefix template split around its `{text}` placeholder.
+#[derive(Debug, Clone, Default)]
+struct Template {
+    prefix: String,
+    suffix: String,
+}
+
+impl Template {
+    fn parse(template: Option<&str>) -> Self {
+        match template {
+            Some(template) => match template.split_once("{text}") {
+                Some((prefix, suffix)) => Self { prefix: prefix.to_string(), suffix: suffix.to_string() },
+                None => Self { prefix: template.to_string(), suffix: String::new() },
+            },
+            None => Self::default(),
+        }
+    }
+
+}
+
+// Cuts texts into token windows that each carry a template's tokens. The template is
+// applied per window rather than to the whole text, so every chunk gets the prefix and
+// suffix, and truncation never cuts them off.
+struct Frame {
+    prefix: Encoding,
+    suffix: Encoding,
+    // Text tokens per window, after the template and the special tokens.
+    window: usize,
+    overlap: usize,
+}
+
+impl Frame {
+    // `tokenizer` must not truncate, or the template tokens could be cut.
+    fn new(tokenizer: &Tokenizer, template: &Template, max_length: usize, overlap: usize) -> Result<Self> {
+        let encode = |text: &str| tokenizer.encode(text, false).map_err(|e| Error::Tokenization(e.to_string()));
+        let (prefix, suffix) = (encode(&template.prefix)?, encode(&template.suffix)?);
+        let special = tokenizer.get_post_processor().map_or(0, |processor| processor.added_tokens(false));
+        let reserved = special + prefix.len() + suffix.len();
+        let window = max_length.saturating_sub(reserved);
+        if window <= 2 * overlap {
+            return Err(Error::InvalidConfig(format!(
+                "a template of {} tokens leaves {} of max_length {} per window, too few for an overlap of {}",
+                prefix.len() + suffix.len(), window, max_length, overlap
+            )));
+        }
+        Ok(Self { prefix, suffix, window, overlap })
+    }
+
+    // Splits the encoding of a bare text into windows that fit once framed. Only the first
+    // one is kept unless `all`, which truncates the text.
+    fn split(&self, mut encoding: Encoding, all: bool) -> Vec<Encoding> {
+        encoding.truncate(self.window, self.overlap, TruncationDirection::Right);
+        windows(encoding, all)
+    }
+
+    // Surrounds a window with the template and the model's special tokens.
+    fn wrap(&self, tokenizer: &Tokenizer, window: &Encoding) -> Result<Encoding> {
+        let framed = Encoding::merge([self.prefix.clone(), window.clone(), self.suffix.clone()], false);
+        tokenizer.post_process(framed, None, true).map_err(|e| Error::Tokenization(e.to_string()))
+    }
+}
+
+// The model I/O resolved against what the session actually declares.
+#[derive(Debug, Clone)]
+struct ModelIo {
+    attention_mask: bool,
+    token_type_ids: bool,
+    output_name: String,
+    pooling: Option<Pooling>,
+}
+
+impl ModelIo {
+    fn resolve(session: &Session, config: &ModelConfig) -> Result<Self> {
+        let has_input = |name: &str| session.inputs.iter().any(|input| input.name == name);
+        let has_output = |name: &str| session.outputs.iter().any(|output| output.name == name);
+
+        if !has_input("input_ids") {
+            return Err(Error::InvalidConfig("model has no 'input_ids' input".to_string()));
+        }
+
+        let token_type_ids = match config.token_type_ids {
+            Some(true) if !has_input("token_type_ids") => {
+                return Err(Error::InvalidConfig("model has no 'token_type_ids' input".to_string()));
+            }
+            Some(enabled) => enabled,
+            None => has_input("token_type_ids"),
+        };
+
+        let output_name = match &config.output_name {
+            Some(name) if has_output(name) => name.clone(),
+            Some(name) => return Err(Error::MissingOutput(name.clone())),
+            None => {
+                // An explicit token-level pooling needs token embeddings, not a pooled output.
+                let preferred: &[&str] = match config.pooling {
+                    Some(pooling) if pooling != Pooling::None => &TOKEN_OUTPUTS,
+                    _ => &KNOWN_OUTPUTS,
+                };
+                preferred.iter()
+                    .find(|name| has_output(name))
+                    .map(|name| name.to_string())
+                    .or_else(|| session.outputs.first().map(|output| output.name.clone()))
+                    .ok_or_else(|| Error::MissingOutput("<none>".to_string()))?
+            }
+        };
+
+        Ok(Self {
+            attention_mask: has_input("attention_mask"),
+            token_type_ids,
+            output_name,
+            pooling: config.pooling,
+        })
+    }
+}
 
 pub struct TextEmbedder {
-    session_pool: Arc<ArrayQueue<Session>>,
+    session_pool: SessionPool<Session>,
     tokenizer: Tokenizer,
+    io: ModelIo,
+    chunking: Option<Chunking>,
+    plain: Frame,
+    query: Frame,
+    document: Frame,
+    pub max_length: usize,
     pub dim: usize,
 }
 
+// Sessions not currently running inference. Callers wait on `available` while all are
+// checked out, instead of spinning. Generic so it can be tested without a model.
+struct SessionPool<T> {
+    idle: Mutex<Vec<T>>,
+    available: Condvar,
+}
+
+impl<T> SessionPool<T> {
+    fn new(sessions: Vec<T>) -> Self {
+        Self { idle: Mutex::new(sessions), available: Condvar::new() }
+    }
+
+    fn checkout(&self) -> PooledSession<'_, T> {
+        let mut idle = self.idle.lock().expect("session pool poisoned");
+        loop {
+            if let Some(session) = idle.pop() {
+                return PooledSession { pool: self, session: Some(session) };
+            }
+            idle = self.available.wait(idle).expect("session pool poisoned");
+        }
+    }
+
+    fn checkin(&self, session: T) {
+        self.idle.lock().expect("session pool poisoned").push(session);
+        self.available.notify_one();
+    }
+}
+
+// Returns the session to the pool when dropped, including on early error returns.
+struct PooledSession<'a, T> {
+    pool: &'a SessionPool<T>,
+    session: Option<T>,
+}
+
+impl<T> Deref for PooledSession<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.session.as_ref().expect("session already returned")
+    }
+}
+
+impl<T> DerefMut for PooledSession<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.session.as_mut().expect("session already returned")
+    }
+}
+
+impl<T> Drop for PooledSession<'_, T> {
+    fn drop(&mut self) {
+        if let Some(session) = self.session.take() {
+            self.pool.checkin(session);
+        }
+    }
+}
+
 impl TextEmbedder {
-    pub fn new(model_path: &str, tokenizer_path: &str) -> Self {
-        let tokenizer = Tokenizer::from_file(tokenizer_path).unwrap();
-        
-        // We use a smaller pool (e.g., 2 or 3) but allow each to use full CPU.
-        // This is usually better for Mac CPUs (Performance vs Efficiency cores).
-        let num_sessions = 2; 
-        let session_pool = Arc::new(ArrayQueue::new(num_sessions));
+    pub fn new(model_path: &str, tokenizer_path: &str) -> Result<Self> {
+        Self::with_config(model_path, tokenizer_path, ModelConfig::default())
+    }
 
+    pub fn with_config(model_path: &str, tokenizer_path: &str, config: ModelConfig) -> Result<Self> {
+        let mut tokenizer = Tokenizer::from_file(tokenizer_path)
+            .map_err(|e| Error::TokenizerLoad(format!("{}: {}", tokenizer_path, e)))?;
+
+        let max_length = config.max_length
+            .or_else(|| tokenizer.get_truncation().map(|truncation| truncation.max_length))
+            .unwrap_or(DEFAULT_MAX_LENGTH);
+        if max_length < MIN_MAX_LENGTH {
+            return Err(Error::InvalidConfig(format!("max_length must be at least {}", MIN_MAX_LENGTH)));
+        }
+        let overlap = config.chunking.map_or(0, |chunking| chunking.overlap);
+        if overlap >= max_length / 2 {
+            return Err(Error::InvalidConfig(format!(
+                "chunk overlap {} must be less than half of max_length {}", overlap, max_length
+            )));
+        }
+        // Texts are cut into windows by their `Frame`, after encoding, so the template
+        // tokens can be reserved in every window.
+        tokenizer.with_truncation(None)
+            .map_err(|e| Error::InvalidConfig(e.to_string()))?;
+        let frame = |template: Option<&str>| Frame::new(&tokenizer, &Template::parse(template), max_length, overlap);
+        let plain = frame(None)?;
+        let query = frame(config.query_prefix.as_deref())?;
+        let document = frame(config.document_prefix.as_deref())?;
+
+        // A few sessions sharing all cores is usually better than one session per core,
+        // especially on Mac CPUs (Performance vs Efficiency cores).
+        let num_sessions = config.num_sessions.unwrap_or(DEFAULT_NUM_SESSIONS);
+        if num_sessions == 0 {
+            return Err(Error::InvalidConfig("num_sessions must be positive".to_string()));
+        }
+        if config.intra_threads == Some(0) || config.inter_threads == Some(0) {
+            return Err(Error::InvalidConfig("thread counts must be positive".to_string()));
+        }
+        let intra_threads = config.intra_threads.unwrap_or((num_cpus::get() / num_sessions).max(1));
+
+        let mut sessions = Vec::with_capacity(num_sessions);
         for _ in 0..num_sessions {
             let session = Session::builder()
-                .unwrap()
-                .with_optimization_level(GraphOptimizationLevel::Level3)
-                .unwrap()
-                // Removing with_intra_threads(1) to let ONNX use the optimal number of threads.
-                .commit_from_file(model_path)
-                .unwrap();
-            session_pool.push(session).ok();
+                .and_then(|builder| builder.with_optimization_level(GraphOptimizationLevel::Level3))
+                .and_then(|builder| builder.with_intra_threads(intra_threads))
+                .and_then(|builder| match config.inter_threads {
+                    Some(threads) => builder.with_inter_threads(threads),
+                    None => Ok(builder),
+                })
+                .and_then(|builder| builder.commit_from_file(model_path))
+                .map_err(Error::ModelLoad)?;
+            sessions.push(session);
         }
+        let io = ModelIo::resolve(&sessions[0], &config)?;
 
-        let mut embedder = Self { 
-            session_pool, 
+        let mut embedder = Self {
+            session_pool: SessionPool::new(sessions),
             tokenizer,
+            io,
+            chunking: config.chunking,
+            plain,
+            query,
+            document,
+            max_length,
             dim: 0,
         };
 
-        let dummy_vec = embedder.embed("test");
+        let dummy_vec = embedder.embed("test")?;
         embedder.dim = dummy_vec.len();
 
-        embedder
+        Ok(embedder)
     }
 
-    pub fn embed(&self, text: &str) -> Vec<f32> {
-        let encoding = self.tokenizer.encode(text, true).unwrap();
-        let ids = encoding.get_ids();
-        let mask = encoding.get_attention_mask();
-        let type_ids = encoding.get_type_ids();
-        let seq_len = ids.len();
+    /// Embeds one text. Inputs longer than `max_length` are truncated, or averaged over
+    /// their token windows with [`ChunkMode::Average`].
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        Ok(self.embed_framed(vec![text.to_string()], &self.plain)?.remove(0))
+    }
 
-        let input_ids_array = Array2::from_shape_fn((1, seq_len), |(_, j)| ids[j] as i64);
-        let attention_mask_array = Array2::from_shape_fn((1, seq_len), |(_, j)| mask[j] as i64);
-        let token_type_ids_array = Array2::from_shape_fn((1, seq_len), |(_, j)| type_ids[j] as i64);
+    /// Embeds a search query, applying the configured query prefix.
+    pub fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        Ok(self.embed_framed(vec![query.to_string()], &self.query)?.remove(0))
+    }
 
-        let mut session = self.get_session();
-        let outputs = session.run(ort::inputs![
-            "input_ids" => Value::from_array(input_ids_array).unwrap(),
-            "attention_mask" => Value::from_array(attention_mask_array).unwrap(),
-            "token_type_ids" => Value::from_array(token_type_ids_array).unwrap(),
-        ]).unwrap();
+    /// Embeds a text for storage as a single vector, applying the configured document prefix.
+    pub fn embed_document(&self, text: &str) -> Result<Vec<f32>> {
+        Ok(self.embed_framed(vec![text.to_string()], &self.document)?.remove(0))
+    }
 
-        let output_tensor = outputs["last_hidden_state"].try_extract_tensor::<f32>().unwrap();
-        let (shape, data) = output_tensor;
-        let shape_usize: Vec<usize> = shape.iter().map(|&d| d as usize).collect();
-        let view = ArrayViewD::from_shape(IxDyn(&shape_usize), data).unwrap();
-        
-        let pooled = view.mean_axis(Axis(1)).unwrap();
-        let mut vector: Vec<f32> = pooled.index_axis(Axis(0), 0).iter().cloned().collect();
-        
-        drop(outputs);
-        self.session_pool.push(session).ok();
-        self.normalize(&mut vector);
-        vector
+    /// Embeds several texts for storage, one vector each, applying the configured document prefix.
+    pub fn embed_document_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        self.embed_framed(texts, &self.document)
     }
 
-    fn get_session(&self) -> Session {
-        loop {
-            if let Some(s) = self.session_pool.pop() {
-                return s;
-            }
-            std::thread::yield_now();
+    pub fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        self.embed_framed(texts, &self.plain)
+    }
+
+    /// Embeds texts for storage, applying the configured document prefix. Each text yields
+    /// a single chunk holding the whole text, unless chunking in [`ChunkMode::Records`] mode
+    /// splits it into one chunk per window.
+    pub fn embed_documents(&self, texts: Vec<String>) -> Result<Vec<Vec<Chunk>>> {
+        if !matches!(self.chunking, Some(Chunking { mode: ChunkMode::Records, .. })) {
+            let vectors = self.embed_document_batch(texts.clone())?;
+            return Ok(texts.into_iter()
+                .zip(vectors)
+                .map(|(text, vector)| vec![Chunk { text, vector }])
+                .collect());
         }
+
+        let windows = self.split(&texts, &self.document, true)?;
+        let vectors = self.run_windows(&self.wrap(&windows, &self.document)?)?;
+
+        Ok(texts.into_iter().zip(windows).zip(vectors)
+            .map(|((text, windows), vectors)| {
+                if windows.len() == 1 {
+                    return vectors.into_iter().map(|vector| Chunk { text: text.clone(), vector }).collect();
+                }
+                windows.iter().zip(vectors)
+                    .map(|(window, vector)| Chunk { text: window_text(&text, window), vector })
+                    .collect()
+            })
+            .collect())
     }
 
-    fn normalize(&self, v: &mut [f32]) {
-        let norm: f32 = v.iter().map(|x| x * x).sum::<f32>().sqrt();
-        if norm > f32::EPSILON {
-            for x in v.iter_mut() {
-                *x /= norm;
+    fn averages_windows(&self) -> bool {
+        matches!(self.chunking, Some(Chunking { mode: ChunkMode::Average, .. }))
+    }
+
+    // Embeds each text framed by `frame`: truncated, or averaged over its windows.
+    fn embed_framed(&self, texts: Vec<String>, frame: &Frame) -> Result<Vec<Vec<f32>>> {
+        if texts.is_empty() { return Ok(vec![]); }
+
+        let windows = self.split(&texts, frame, self.averages_windows())?;
+        let vectors = self.run_windows(&self.wrap(&windows, frame)?)?;
+        Ok(vectors.into_iter().map(|vectors| self.average(vectors)).collect())
+    }
+
+    // The bare token windows of every text, sized to fit once framed.
+    fn split(&self, texts: &[String], frame: &Frame, all: bool) -> Result<Vec<Vec<Encoding>>> {
+        let inputs: Vec<&str> = texts.iter().map(String::as_str).collect();
+        let encodings = self.tokenizer.encode_batch(inputs, false)
+            .map_err(|e| Error::Tokenization(e.to_string()))?;
+        Ok(encodings.into_iter().map(|encoding| frame.split(encoding, all)).collect())
+    }
+
+    fn wrap(&self, windows: &[Vec<Encoding>], frame: &Frame) -> Result<Vec<Vec<Encoding>>> {
+        windows.iter()
+            .map(|text_windows| text_windows.iter().map(|window| frame.wrap(&self.tokenizer, window)).collect())
+            .collect()
+    }
+
+    // Runs the windows of all texts as one batch and groups the vectors back per text.
+    fn run_windows(&self, windows: &[Vec<Encoding>]) -> Result<Vec<Vec<Vec<f32>>>> {
+        let flat: Vec<&Encoding> = windows.iter().flatten().collect();
+        let mut vectors = self.run_bucketed(&flat)?.into_iter();
+        Ok(windows.iter().map(|text_windows| vectors.by_ref().take(text_windows.len()).collect()).collect())
+    }
+
+    fn average(&self, mut vectors: Vec<Vec<f32>>) -> Vec<f32> {
+        if vectors.len() == 1 {
+            return vectors.remove(0);
+        }
+        let mut sum = vec![0.0; vectors.first().map_or(0, Vec::len)];
+        for vector in &vectors {
+            for (acc, x) in sum.iter_mut().zip(vector) {
+                *acc += x;
             }
         }
+        // The mean and the sum point the same way, so normalizing the sum is enough.
+        self.normalize(&mut sum);
+        sum
     }
 
-    pub fn embed_batch(&self, texts: Vec<String>) -> Vec<Vec<f32>> {
-        if texts.is_empty() { return vec![]; }
-        if texts.len() == 1 { return vec![self.embed(&texts[0])]; }
+    // Runs similar-length encodings together, so short inputs are not padded to the length
+    // of a long one, and returns the vectors in the original order.
+    fn run_bucketed(&self, encodings: &[&Encoding]) -> Result<Vec<Vec<f32>>> {
+        let lengths: Vec<usize> = encodings.iter().map(|encoding| encoding.get_ids().len()).collect();
+        let buckets = length_buckets(&lengths, MAX_BUCKET_SIZE);
+        if buckets.len() == 1 {
+            return self.run(encodings);
+        }
+
+        let mut vectors = vec![Vec::new(); encodings.len()];
+        for bucket in buckets {
+            let batch: Vec<&Encoding> = bucket.iter().map(|&i| encodings[i]).collect();
+            for (i, vector) in bucket.into_iter().zip(self.run(&batch)?) {
+                vectors[i] = vector;
+            }
+        }
+        Ok(vectors)
+    }
 
-        let encodings = self.tokenizer.encode_batch(texts, true).unwrap();
+    // Pads the encodings into one batch, runs the model once and pools each item.
+    fn run(&self, encodings: &[&Encoding]) -> Result<Vec<Vec<f32>>> {
         let batch_size = encodings.len();
         let max_len = encodings.iter().map(|e| e.get_ids().len()).max().unwrap_or(0);
 
@@ -106,7 +528,7 @@ impl TextEmbedder {
         let mut attention_mask = Vec::with_capacity(batch_size * max_len);
         let mut token_type_ids = Vec::with_capacity(batch_size * max_len);
 
-        for encoding in &encodings {
+        for &encoding in encodings {
             let ids = encoding.get_ids();
             let len = ids.len();
             input_ids.extend(ids.iter().map(|&id| id as i64));
@@ -119,35 +541,278 @@ impl TextEmbedder {
             }
         }
 
-        let input_ids_array = Array2::from_shape_vec((batch_size, max_len), input_ids).unwrap();
-        let attention_mask_array = Array2::from_shape_vec((batch_size, max_len), attention_mask).unwrap();
-        let token_type_ids_array = Array2::from_shape_vec((batch_size, max_len), token_type_ids).unwrap();
+        let shape = (batch_size, max_len);
+        let mut inputs: Vec<(&str, SessionInputValue)> = vec![
+            ("input_ids", Value::from_array(Array2::from_shape_vec(shape, input_ids)?)?.into()),
+        ];
+        if self.io.attention_mask {
+            inputs.push(("attention_mask", Value::from_array(Array2::from_shape_vec(shape, attention_mask)?)?.into()));
+        }
+        if self.io.token_type_ids {
+            inputs.push(("token_type_ids", Value::from_array(Array2::from_shape_vec(shape, token_type_ids)?)?.into()));
+        }
 
-        let mut session = self.get_session();
-        let outputs = session.run(ort::inputs![
-            "input_ids" => Value::from_array(input_ids_array).unwrap(),
-            "attention_mask" => Value::from_array(attention_mask_array).unwrap(),
-            "token_type_ids" => Value::from_array(token_type_ids_array).unwrap(),
-        ]).unwrap();
+        let mut session = self.session_pool.checkout();
+        let outputs = session.run(inputs)?;
 
-        let output_tensor = outputs["last_hidden_state"].try_extract_tensor::<f32>().unwrap();
+        let output_tensor = outputs.get(&self.io.output_name)
+            .ok_or_else(|| Error::MissingOutput(self.io.output_name.clone()))?
+            .try_extract_tensor::<f32>()?;
         let (shape, data) = output_tensor;
         let shape_usize: Vec<usize> = shape.iter().map(|&d| d as usize).collect();
-        let view = ArrayViewD::from_shape(IxDyn(&shape_usize), data).unwrap();
-        
+        let view = ArrayViewD::from_shape(IxDyn(&shape_usize), data)?;
+
         let mut results = Vec::with_capacity(batch_size);
-        for i in 0..batch_size {
+        for (i, encoding) in encodings.iter().enumerate() {
             let item_view = view.index_axis(Axis(0), i);
-            let original_len = encodings[i].get_ids().len();
-            let unpadded_item = item_view.slice(s![0..original_len, ..]);
-            let pooled = unpadded_item.mean_axis(Axis(0)).unwrap();
-            let mut vector: Vec<f32> = pooled.iter().cloned().collect();
+            let mut vector: Vec<f32> = match item_view.ndim() {
+                // Already pooled by the model, e.g. `sentence_embedding`.
+                1 => match self.io.pooling {
+                    None | Some(Pooling::None) => item_view.iter().cloned().collect(),
+                    Some(pooling) => {
+                        return Err(Error::InvalidConfig(format!(
+                            "output '{}' is already pooled, cannot apply {:?} pooling",
+                            self.io.output_name, pooling
+                        )));
+                    }
+                },
+                // Token embeddings: pool over the unpadded tokens.
+                2 => {
+                    let tokens = item_view.into_dimensionality::<ndarray::Ix2>()?;
+                    let len = encoding.get_attention_mask().iter().filter(|&&m| m != 0).count();
+                    self.io.pooling.unwrap_or(Pooling::Mean).apply(tokens, len)?
+                }
+                _ => {
+                    return Err(Error::InvalidConfig(format!(
+                        "output '{}' has unsupported shape {:?}",
+                        self.io.output_name, shape_usize
+                    )));
+                }
+            };
             self.normalize(&mut vector);
             results.push(vector);
         }
 
         drop(outputs);
-        self.session_pool.push(session).ok();
-        results
+        Ok(results)
+    }
+
+    fn normalize(&self, v: &mut [f32]) {
+        let norm: f32 = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > f32::EPSILON {
+            for x in v.iter_mut() {
+                *x /= norm;
+            }
+        }
+    }
+}
+
+impl Embedder for TextEmbedder {
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        TextEmbedder::embed(self, text)
+    }
+
+    fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        TextEmbedder::embed_batch(self, texts)
+    }
+
+    fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        TextEmbedder::embed_query(self, query)
+    }
+
+    fn embed_document(&self, text: &str) -> Result<Vec<f32>> {
+        TextEmbedder::embed_document(self, text)
+    }
+
+    fn embed_document_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        TextEmbedder::embed_document_batch(self, texts)
+    }
+
+    fn embed_documents(&self, texts: Vec<String>) -> Result<Vec<Vec<Chunk>>> {
+        TextEmbedder::embed_documents(self, texts)
+    }
+}
+
+// Groups input indices by token length: sorted ascending, a bucket is closed when it is full
+// or when the next input is more than twice as long as its shortest one.
+fn length_buckets(lengths: &[usize], max_bucket_size: usize) -> Vec<Vec<usize>> {
+    let mut order: Vec<usize> = (0..lengths.len()).collect();
+    order.sort_by_key(|&i| lengths[i]);
+
+    let mut buckets: Vec<Vec<usize>> = Vec::new();
+    for i in order {
+        match buckets.last_mut() {
+            Some(bucket) if bucket.len() < max_bucket_size
+                && lengths[i] <= (2 * lengths[bucket[0]]).max(MIN_BUCKET_LENGTH) => bucket.push(i),
+            _ => buckets.push(vec![i]),
+        }
+    }
+    buckets
+}
+
+// The encoding's token windows: the first (truncated) one, or all of them.
+fn windows(mut encoding: Encoding, all: bool) -> Vec<Encoding> {
+    let overflowing = encoding.take_overflowing();
+    let mut windows = vec![encoding];
+    if all {
+        windows.extend(overflowing);
+    }
+    windows
+}
+
+// The part of `text` covered by the window's non-special tokens.
+fn window_text(text: &str, window: &Encoding) -> String {
+    let spans: Vec<(usize, usize)> = window.get_offsets().iter()
+        .zip(window.get_special_tokens_mask())
+        .filter(|&(_, &special)| special == 0)
+        .map(|(&offsets, _)| offsets)
+        .collect();
+    let start = spans.iter().map(|&(start, _)| start).min().unwrap_or(0);
+    let end = spans.iter().map(|&(_, end)| end).max().unwrap_or(0);
+    text.get(start..end).unwrap_or_default().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_pooling_ignores_padding() {
+        // Three real tokens followed by one padding token.
+        let tokens = array![[1.0, 0.0], [3.0, 4.0], [2.0, -2.0], [9.0, 9.0]];
+        let view = tokens.view();
+
+        assert_eq!(Pooling::Cls.apply(view, 3).unwrap(), vec![1.0, 0.0]);
+        assert_eq!(Pooling::LastToken.apply(view, 3).unwrap(), vec![2.0, -2.0]);
+        assert_eq!(Pooling::Mean.apply(view, 3).unwrap(), vec![2.0, 2.0 / 3.0]);
+        assert_eq!(Pooling::Max.apply(view, 3).unwrap(), vec![3.0, 4.0]);
+        assert!(Pooling::None.apply(view, 3).is_err());
+        assert!(Pooling::Mean.apply(view, 0).is_err());
+    }
+
+    #[test]
+    fn test_session_pool_wakes_waiter() {
+        use std::sync::mpsc;
+        use std::thread;
+        use std::time::Duration;
+
+        let pool = SessionPool::new(vec![1]);
+        let held = pool.checkout();
+        let (done, finished) = mpsc::channel();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let session = pool.checkout();
+                done.send(*session).unwrap();
+            });
+            // The waiter is parked while the only session is checked out.
+            assert!(finished.recv_timeout(Duration::from_millis(100)).is_err());
+            drop(held);
+            assert_eq!(finished.recv_timeout(Duration::from_secs(5)).unwrap(), 1);
+        });
+        assert_eq!(pool.idle.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_chunk_windows_overlap() {
+        use tokenizers::TruncationParams;
+        use tokenizers::models::wordlevel::WordLevel;
+        use tokenizers::pre_tokenizers::whitespace::Whitespace;
+
+        let vocab = ["[UNK]", "a", "b", "c", "d", "e", "f", "g"].iter()
+            .enumerate()
+            .map(|(id, token)| (token.to_string(), id as u32))
+            .collect();
+        let model = WordLevel::builder().vocab(vocab).unk_token("[UNK]".to_string()).build().unwrap();
+        let mut tokenizer = Tokenizer::new(model);
+        tokenizer.with_pre_tokenizer(Some(Whitespace {}));
+        tokenizer.with_truncation(Some(TruncationParams { max_length: 3, stride: 1, ..Default::default() }))
+            .unwrap();
+
+        let text = "a b c d e f g";
+        let encoding = tokenizer.encode(text, true).unwrap();
+
+        let truncated = windows(encoding.clone(), false);
+        assert_eq!(truncated.len(), 1);
+        assert_eq!(window_text(text, &truncated[0]), "a b c");
+
+        let texts: Vec<String> = windows(encoding, true).iter().map(|w| window_text(text, w)).collect();
+        assert_eq!(texts, vec!["a b c", "c d e", "e f g"]);
+    }
+
+    #[test]
+    fn test_length_buckets() {
+        let lengths = [300, 5, 12, 40, 7, 70, 512];
+        assert_eq!(length_buckets(&lengths, 64), vec![vec![1, 4, 2], vec![3, 5], vec![0, 6]]);
+        assert_eq!(length_buckets(&lengths, 2), vec![vec![1, 4], vec![2], vec![3, 5], vec![0, 6]]);
+        assert_eq!(length_buckets(&[9, 9, 9], 64), vec![vec![0, 1, 2]]);
+    }
+
+    #[test]
+    fn test_prefix_template() {
+        let prefix = Template::parse(Some("query: "));
+        assert_eq!((prefix.prefix.as_str(), prefix.suffix.as_str()), ("query: ", ""));
+
+        let instruct = Template::parse(Some("Instruct: find errors\nQuery: {text}</s>"));
+        assert_eq!((instruct.prefix.as_str(), instruct.suffix.as_str()), ("Instruct: find errors\nQuery: ", "</s>"));
+
+        let none = Template::parse(None);
+        assert_eq!((none.prefix.as_str(), none.suffix.as_str()), ("", ""));
+    }
+
+    #[test]
+    fn test_template_frames_every_window() {
+        use tokenizers::models::wordlevel::WordLevel;
+        use tokenizers::pre_tokenizers::whitespace::Whitespace;
+        use tokenizers::processors::template::TemplateProcessing;
+
+        let vocab = ["[UNK]", "a", "b", "c", "d", "e", "f", "g", "passage", ":", "end", "[CLS]", "[SEP]"].iter()
+            .enumerate()
+            .map(|(id, token)| (token.to_string(), id as u32))
+            .collect();
+        let model = WordLevel::builder().vocab(vocab).unk_token("[UNK]".to_string()).build().unwrap();
+        let mut tokenizer = Tokenizer::new(model);
+        tokenizer.with_pre_tokenizer(Some(Whitespace {}));
+        let processor = TemplateProcessing::builder()
+            .try_single("[CLS] $A [SEP]").unwrap()
+            .special_tokens(vec![("[CLS]", 11), ("[SEP]", 12)])
+            .build()
+            .unwrap();
+        tokenizer.with_post_processor(Some(processor));
+
+        let text = "a b c d e f g";
+        let encoding = tokenizer.encode(text, false).unwrap();
+
+        // Two special tokens and two prefix tokens leave four text tokens per window.
+        let frame = Frame::new(&tokenizer, &Template::parse(Some("passage: ")), 8, 1).unwrap();
+        let windows = frame.split(encoding.clone(), true);
+        let texts: Vec<String> = windows.iter().map(|window| window_text(text, window)).collect();
+        assert_eq!(texts, vec!["a b c d", "d e f g"]);
+        for window in &windows {
+            let ids = frame.wrap(&tokenizer, window).unwrap().get_ids().to_vec();
+            assert!(ids.len() <= 8);
+            assert_eq!(ids[..3], [11, 8, 9]);
+            assert_eq!(ids.last(), Some(&12));
+        }
+
+        // A suffix survives truncation.
+        let frame = Frame::new(&tokenizer, &Template::parse(Some("{text} end")), 6, 0).unwrap();
+        let truncated = frame.split(encoding, false);
+        assert_eq!(truncated.len(), 1);
+        assert_eq!(frame.wrap(&tokenizer, &truncated[0]).unwrap().get_ids(), [11, 1, 2, 3, 10, 12]);
+
+        // No room left for text.
+        assert!(Frame::new(&tokenizer, &Template::parse(Some("passage: ")), 4, 0).is_err());
+    }
+
+    #[test]
+    fn test_pooling_from_str() {
+        assert_eq!("CLS".parse::<Pooling>().unwrap(), Pooling::Cls);
+        assert_eq!("last_token".parse::<Pooling>().unwrap(), Pooling::LastToken);
+        assert!("median".parse::<Pooling>().is_err());
     }
 }
@@ -0,0 +1,167 @@
+use crate::error::{Error, Result};
+use crate::models::{Attributes, VectorRecord};
+
+/// One embedded piece of a document: the whole text, or a single token window of it.
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub text: String,
+    pub vector: Vec<f32>,
+}
+
+/// Builds the records for an embedded document. A document split into several chunks
+/// becomes one record per chunk, linked to `id` (see [`VectorRecord::into_chunk_of`]).
+pub fn document_records(
+    id: String,
+    chunks: Vec<Chunk>,
+    timestamp: Option<u64>,
+    attributes: Attributes,
+) -> Vec<VectorRecord> {
+    let chunked = chunks.len() > 1;
+    chunks.into_iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let record = match timestamp {
+                Some(ts) => VectorRecord::new_with_event_time(id.clone(), chunk.vector, chunk.text, ts),
+                None => VectorRecord::new(id.clone(), chunk.vector, chunk.text),
+            };
+            let record = record.with_attributes(attributes.clone());
+            if chunked { record.into_chunk_of(&id, i) } else { record }
+        })
+        .collect()
+}
+
+/// Turns text into vectors. Implemented by the ONNX `TextEmbedder` (with the `embedder`
+/// feature) and by [`HashEmbedder`] for offline use.
+pub trait Embedder: Send + Sync {
+    /// Length of the vectors this embedder produces.
+    fn dim(&self) -> usize;
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>>;
+
+    fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        texts.iter().map(|text| self.embed(text)).collect()
+    }
+
+    /// Embeds a search query. Asymmetric models override this to add their query prefix.
+    fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        self.embed(query)
+    }
+
+    /// Embeds a text for storage as a single vector.
+    fn embed_document(&self, text: &str) -> Result<Vec<f32>> {
+        self.embed(text)
+    }
+
+    /// Embeds several texts for storage, one vector each.
+    fn embed_document_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        texts.iter().map(|text| self.embed_document(text)).collect()
+    }
+
+    /// Embeds texts for storage, as one or more chunks per text.
+    fn embed_documents(&self, texts: Vec<String>) -> Result<Vec<Vec<Chunk>>> {
+        let vectors = self.embed_batch(texts.clone())?;
+        Ok(texts.into_iter()
+            .zip(vectors)
+            .map(|(text, vector)| vec![Chunk { text, vector }])
+            .collect())
+    }
+}
+
+/// A deterministic embedder that needs no model: every lowercased word and character
+/// trigram is hashed to a signed position of the vector (the "hashing trick").
+/// Texts sharing words get similar vectors, which is enough to exercise ingestion
+/// and search end to end in tests and offline setups. It has no semantic understanding.
+#[derive(Debug, Clone)]
+pub struct HashEmbedder {
+    dim: usize,
+    seed: u64,
+}
+
+impl HashEmbedder {
+    pub fn new(dim: usize) -> Result<Self> {
+        Self::with_seed(dim, 0)
+    }
+
+    /// Different seeds give unrelated vector spaces.
+    pub fn with_seed(dim: usize, seed: u64) -> Result<Self> {
+        if dim == 0 {
+            return Err(Error::InvalidConfig("dimension must be positive".to_string()));
+        }
+        Ok(Self { dim, seed })
+    }
+
+    fn add_feature(&self, vector: &mut [f32], feature: &str, weight: f32) {
+        let hash = fxhash::hash64(&(self.seed, feature));
+        let index = (hash % self.dim as u64) as usize;
+        // The top bit decides the sign, so colliding features tend to cancel out.
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[index] += sign * weight;
+    }
+}
+
+impl Embedder for HashEmbedder {
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let mut vector = vec![0.0; self.dim];
+        let text = text.to_lowercase();
+        for word in text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()) {
+            self.add_feature(&mut vector, word, 1.0);
+            // Trigrams make related forms ("timeout", "timeouts") land close together.
+            let chars: Vec<char> = format!("#{}#", word).chars().collect();
+            for trigram in chars.windows(3) {
+                self.add_feature(&mut vector, &trigram.iter().collect::<String>(), 0.5);
+            }
+        }
+
+        let norm: f32 = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > f32::EPSILON {
+            for x in vector.iter_mut() {
+                *x /= norm;
+            }
+        }
+        Ok(vector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ShardedCircularBuffer;
+    use crate::models::VectorRecord;
+
+    #[test]
+    fn test_hash_embedder_is_deterministic() {
+        let embedder = HashEmbedder::new(64).unwrap();
+        let a = embedder.embed("Disk full on /var").unwrap();
+        assert_eq!(a.len(), 64);
+        assert_eq!(a, embedder.embed("disk FULL on /var").unwrap());
+        assert_ne!(a, HashEmbedder::with_seed(64, 1).unwrap().embed("Disk full on /var").unwrap());
+
+        let norm: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-5);
+        assert!(matches!(HashEmbedder::new(0), Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_hash_embedder_end_to_end() {
+        let embedder: Box<dyn Embedder> = Box::new(HashEmbedder::new(256).unwrap());
+        let buffer = ShardedCircularBuffer::new(2, 16).unwrap();
+
+        let logs = [
+            "database connection timeout after 30s",
+            "user logged in successfully",
+            "disk usage at 91 percent on /var",
+        ];
+        let vectors = embedder.embed_batch(logs.iter().map(|log| log.to_string()).collect()).unwrap();
+        for (i, (log, vector)) in logs.iter().zip(vectors).enumerate() {
+            buffer.insert(VectorRecord::new(format!("log_{}", i), vector, log.to_string())).unwrap();
+        }
+
+        let query = embedder.embed_query("connection timeout").unwrap();
+        let results = buffer.search(&query, 1).unwrap();
+        assert_eq!(results[0].0.metadata, logs[0]);
+    }
+}
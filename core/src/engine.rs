@@ -1,70 +1,855 @@
 use arc_swap::ArcSwapOption;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
-use crate::models::VectorRecord;
+use std::alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout};
+use std::hint;
+use std::ptr::NonNull;
+use std::sync::atomic::{fence, AtomicI8, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::thread;
+use std::time::Duration;
+use crate::error::{Error, Result};
+use crate::filter::Filter;
+use crate::models::{now_timestamp, VectorRecord};
+use crate::search::{normalize, BinaryCode, Metric, QuantizedVector, ScoringMode};
 
 pub const DEFAULT_NUM_SHARDS: usize = 16;
 pub const DEFAULT_SHARD_SIZE: usize = 1024;
+/// Candidates per requested result kept by the Hamming scan of binary storage.
+pub const DEFAULT_OVERSAMPLE: usize = 8;
+
+/// How record vectors are held in memory and compared during search.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VectorStorage {
+    /// Full-precision floats, scored exactly.
+    #[default]
+    F32,
+    /// int8 codes with a per-vector scale, scored with an integer dot product.
+    /// Quantized storage approximates angles, so it requires the cosine or dot metric.
+    /// With `rerank: None` the float vectors are dropped on insert, cutting vector memory
+    /// to a quarter. With `rerank: Some(n)` they are kept, and the `n * k` best int8
+    /// candidates are re-scored with their float vectors before the top `k` are returned.
+    Int8 { rerank: Option<usize> },
+    /// A sign-bit code per vector next to the float vector. Search is two-staged: a popcount
+    /// Hamming scan over all slots keeps the `oversample * k` closest codes, which are then
+    /// re-scored exactly with the buffer's metric.
+    Binary { oversample: usize },
+}
 
 pub struct Shard {
-    buffer: Vec<ArcSwapOption<VectorRecord>>,
+    records: Vec<ArcSwapOption<VectorRecord>>,
+    // One seqlock per slot, odd while a writer updates the slot's record and vectors.
+    seqs: Vec<AtomicU64>,
+    // Allocated on the first insert, once the dimension is known.
+    arena: OnceLock<Arena>,
+    // Serializes upserts, so two upserts of one id can't both miss the other's copy.
+    upsert: Mutex<()>,
     index: AtomicUsize,
     size: usize,
 }
 
 impl Shard {
     fn new(size: usize) -> Self {
-        let mut buffer = Vec::with_capacity(size);
+        let mut records = Vec::with_capacity(size);
+        let mut seqs = Vec::with_capacity(size);
         for _ in 0..size {
-            buffer.push(ArcSwapOption::from(None));
+            records.push(ArcSwapOption::from(None));
+            seqs.push(AtomicU64::new(0));
         }
         Self {
-            buffer,
+            records,
+            seqs,
+            arena: OnceLock::new(),
+            upsert: Mutex::new(()),
             index: AtomicUsize::new(0),
             size,
         }
     }
 
-    fn insert(&self, record: Arc<VectorRecord>) {
+    fn insert(&self, record: Arc<VectorRecord>, vectors: &Encoded) {
         let pos = self.index.fetch_add(1, Ordering::SeqCst) % self.size;
-        self.buffer[pos].store(Some(record));
+        let locked = self.lock(pos);
+        self.store(pos, Some((record, vectors)));
+        self.unlock(pos, locked);
+    }
+
+    // Returns the slot and record holding `id`, preferring the most recent copy
+    // if the ring contains duplicates inserted via `insert`.
+    fn find(&self, id: &str) -> Option<(usize, Arc<VectorRecord>)> {
+        let mut found: Option<(usize, Arc<VectorRecord>)> = None;
+        for (pos, slot) in self.records.iter().enumerate() {
+            if let Some(record) = &*slot.load()
+                && record.id == id
+                && found.as_ref().is_none_or(|(_, best)| record.ingested_at >= best.ingested_at)
+            {
+                found = Some((pos, Arc::clone(record)));
+            }
+        }
+        found
+    }
+
+    fn remove(&self, id: &str) -> usize {
+        let mut removed = 0;
+        for pos in 0..self.size {
+            if let Some(record) = self.records[pos].load_full()
+                && record.id == id
+                && self.replace_if(pos, &record, None)
+            {
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    // Replaces the record with the same id in place, so the stale copy stops competing
+    // in search results. Any other copies are cleared. Falls back to a regular insert
+    // when the id is not present (or its slot was overwritten in the meantime).
+    fn upsert(&self, record: Arc<VectorRecord>, vectors: &Encoded) {
+        let _serialized = self.upsert.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut replaced = false;
+        for pos in 0..self.size {
+            if let Some(current) = self.records[pos].load_full()
+                && current.id == record.id
+            {
+                let new = if replaced { None } else { Some((Arc::clone(&record), vectors)) };
+                if self.replace_if(pos, &current, new) {
+                    replaced = true;
+                }
+            }
+        }
+        if !replaced {
+            self.insert(record, vectors);
+        }
+    }
+
+    // Clears every slot whose record was ingested before `cutoff`. A slot is only
+    // cleared if it still holds the expired record, so a concurrent insert is never lost.
+    fn evict_older_than(&self, cutoff: u64) -> usize {
+        let mut evicted = 0;
+        for pos in 0..self.size {
+            if let Some(record) = self.records[pos].load_full()
+                && record.ingested_at < cutoff
+                && self.replace_if(pos, &record, None)
+            {
+                evicted += 1;
+            }
+        }
+        evicted
+    }
+
+    // Stores `new` in slot `pos` if it still holds `expected`.
+    fn replace_if(&self, pos: usize, expected: &Arc<VectorRecord>, new: Option<(Arc<VectorRecord>, &Encoded)>) -> bool {
+        let locked = self.lock(pos);
+        let current = self.records[pos].load();
+        let matches = current.as_ref().is_some_and(|current| Arc::ptr_eq(current, expected));
+        if matches {
+            self.store(pos, new);
+        }
+        self.unlock(pos, locked);
+        matches
+    }
+
+    // Must be called between `lock` and `unlock` of slot `pos`, so readers discard
+    // anything they copied from the row meanwhile.
+    fn store(&self, pos: usize, new: Option<(Arc<VectorRecord>, &Encoded)>) {
+        let arena = self.arena.get().expect("arena is allocated before the first write");
+        match new {
+            Some((record, vectors)) => {
+                arena.write(pos, &record, vectors);
+                self.records[pos].store(Some(record));
+            }
+            None => {
+                arena.clear(pos);
+                self.records[pos].store(None);
+            }
+        }
+    }
+
+    // Clears slot `pos` if no writer touched it since `read` returned `seq`.
+    fn clear_if_unchanged(&self, pos: usize, seq: u64) -> bool {
+        if !self.try_lock(pos, seq) {
+            return false;
+        }
+        self.store(pos, None);
+        self.unlock(pos, seq + 1);
+        true
+    }
+
+    // Takes the slot's seqlock for writing. Writers only contend when the ring wraps
+    // onto a slot that is still being written, so the spin is short.
+    fn lock(&self, pos: usize) -> u64 {
+        loop {
+            let current = self.seqs[pos].load(Ordering::Relaxed);
+            if current.is_multiple_of(2) && self.try_lock(pos, current) {
+                return current + 1;
+            }
+            hint::spin_loop();
+        }
+    }
+
+    // Takes the seqlock if its sequence is still `current`, which must be even.
+    fn try_lock(&self, pos: usize, current: u64) -> bool {
+        let locked = self.seqs[pos]
+            .compare_exchange(current, current + 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok();
+        if locked {
+            fence(Ordering::Release);
+        }
+        locked
+    }
+
+    fn unlock(&self, pos: usize, locked: u64) {
+        self.seqs[pos].store(locked + 1, Ordering::Release);
+    }
+
+    // Runs `read` on a consistent snapshot of slot `pos`, retrying if a writer changed the
+    // slot meanwhile. `read` may copy a torn arena row, but its result is then discarded.
+    // Also returns the slot's sequence, which changes whenever the slot is written.
+    fn read<R>(&self, pos: usize, mut read: impl FnMut() -> R) -> (R, u64) {
+        let seq = &self.seqs[pos];
+        loop {
+            let before = seq.load(Ordering::Acquire);
+            if before.is_multiple_of(2) {
+                let result = read();
+                fence(Ordering::Acquire);
+                if seq.load(Ordering::Relaxed) == before {
+                    return (result, before);
+                }
+            }
+            hint::spin_loop();
+        }
+    }
+}
+
+// Rows start on a cache line, which also satisfies the alignment of any SIMD load.
+const ROW_ALIGN: usize = 64;
+
+/// Element types an arena column can hold. Each element lives in an atomic cell, so a
+/// reader racing a writer copies a torn row instead of causing a data race.
+///
+/// # Safety
+/// The all-zero bit pattern must be a valid `Cell`.
+unsafe trait Pod: Copy + Default {
+    type Cell: Sync;
+
+    fn load(cell: &Self::Cell) -> Self;
+    fn store(cell: &Self::Cell, value: Self);
+}
+
+unsafe impl Pod for f32 {
+    type Cell = AtomicU32;
+
+    fn load(cell: &AtomicU32) -> f32 {
+        f32::from_bits(cell.load(Ordering::Relaxed))
+    }
+
+    fn store(cell: &AtomicU32, value: f32) {
+        cell.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+unsafe impl Pod for i8 {
+    type Cell = AtomicI8;
+
+    fn load(cell: &AtomicI8) -> i8 {
+        cell.load(Ordering::Relaxed)
+    }
+
+    fn store(cell: &AtomicI8, value: i8) {
+        cell.store(value, Ordering::Relaxed);
+    }
+}
+
+unsafe impl Pod for u64 {
+    type Cell = AtomicU64;
+
+    fn load(cell: &AtomicU64) -> u64 {
+        cell.load(Ordering::Relaxed)
+    }
+
+    fn store(cell: &AtomicU64, value: u64) {
+        cell.store(value, Ordering::Relaxed);
+    }
+}
+
+// A fixed block of `slots` rows of `width` elements in one allocation.
+struct Column<T: Pod> {
+    ptr: NonNull<T::Cell>,
+    layout: Layout,
+    slots: usize,
+    width: usize,
+    stride: usize,
+}
+
+// SAFETY: the column owns its allocation and only hands out shared references to
+// atomic cells.
+unsafe impl<T: Pod> Send for Column<T> {}
+unsafe impl<T: Pod> Sync for Column<T> {}
+
+impl<T: Pod> Column<T> {
+    // Pads every row to a multiple of ROW_ALIGN bytes, so each row is aligned.
+    fn aligned(slots: usize, width: usize) -> Self {
+        Self::new(slots, width, true)
+    }
+
+    fn packed(slots: usize, width: usize) -> Self {
+        Self::new(slots, width, false)
+    }
+
+    // The row stride and allocation of a column, or `None` if it cannot be allocated.
+    fn layout(slots: usize, width: usize, aligned: bool) -> Option<(usize, Layout)> {
+        let stride = if aligned {
+            let per_line = ROW_ALIGN / std::mem::size_of::<T::Cell>();
+            width.div_ceil(per_line).checked_mul(per_line)?
+        } else {
+            width
+        };
+        let size = slots.checked_mul(stride)?.checked_mul(std::mem::size_of::<T::Cell>())?;
+        Some((stride, Layout::from_size_align(size, ROW_ALIGN).ok()?))
+    }
+
+    fn new(slots: usize, width: usize, aligned: bool) -> Self {
+        assert!(slots > 0 && width > 0, "empty column");
+        let (stride, layout) = Self::layout(slots, width, aligned).expect("arena size is checked by Arena::check");
+        // SAFETY: the layout has a non-zero size, and zeroed memory is a valid `T::Cell`.
+        let ptr = unsafe { alloc_zeroed(layout) } as *mut T::Cell;
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| handle_alloc_error(layout));
+        Self { ptr, layout, slots, width, stride }
+    }
+
+    fn cells(&self, pos: usize) -> &[T::Cell] {
+        assert!(pos < self.slots, "slot {} out of bounds", pos);
+        // SAFETY: in bounds, zero-initialized, and only ever accessed through shared
+        // references to atomics.
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr().add(pos * self.stride), self.width) }
+    }
+
+    // Copies the row into `out`. Outside the slot's seqlock the copy may be torn, so
+    // readers must validate it with `Shard::read`.
+    fn read(&self, pos: usize, out: &mut Vec<T>) {
+        out.clear();
+        out.extend(self.cells(pos).iter().map(T::load));
+    }
+
+    fn get(&self, pos: usize) -> T {
+        T::load(&self.cells(pos)[0])
+    }
+
+    // Callers hold the slot's seqlock, so readers discard rows they copied meanwhile.
+    fn write(&self, pos: usize, values: &[T]) {
+        for (cell, &value) in self.cells(pos).iter().zip(values) {
+            T::store(cell, value);
+        }
+    }
+}
+
+impl<T: Pod> Drop for Column<T> {
+    fn drop(&mut self) {
+        // SAFETY: allocated in `with_stride` with this layout.
+        unsafe { dealloc(self.ptr.as_ptr() as *mut u8, self.layout) };
+    }
+}
+
+// The representations of one vector that a storage mode keeps.
+struct Encoded {
+    vector: Vec<f32>,
+    int8: Option<QuantizedVector>,
+    bits: Option<BinaryCode>,
+}
+
+// A shard's vectors and the record times a search checks, kept apart from the records in
+// contiguous `[slots × dim]` columns so the search scan streams through memory instead of
+// chasing a pointer per record. Which vector columns exist depends on the storage mode.
+struct Arena {
+    timestamps: Column<u64>,
+    // Zero for an empty slot, since a stored record is always stamped with the current time.
+    ingested_at: Column<u64>,
+    vectors: Option<Column<f32>>,
+    int8: Option<Column<i8>>,
+    scales: Option<Column<f32>>,
+    bits: Option<Column<u64>>,
+}
+
+// Which vector columns a storage mode keeps: (floats, int8 codes and scales, binary codes).
+fn columns(storage: VectorStorage) -> (bool, bool, bool) {
+    (
+        !matches!(storage, VectorStorage::Int8 { rerank: None }),
+        matches!(storage, VectorStorage::Int8 { .. }),
+        matches!(storage, VectorStorage::Binary { .. }),
+    )
+}
+
+impl Arena {
+    // Fails if any column of an arena this size could not be allocated, so a bad shard
+    // size or dimension is rejected up front instead of panicking on the first insert.
+    fn check(slots: usize, dim: usize, storage: VectorStorage) -> Result<()> {
+        let (floats, int8, binary) = columns(storage);
+        let fits = Column::<u64>::layout(slots, 1, false).is_some()
+            && (!floats || Column::<f32>::layout(slots, dim, true).is_some())
+            && (!int8 || Column::<i8>::layout(slots, dim, true).is_some())
+            && (!binary || Column::<u64>::layout(slots, dim.div_ceil(64), true).is_some());
+        if !fits {
+            return Err(Error::InvalidConfig(format!(
+                "a shard of {} slots of dimension {} is too large",
+                slots, dim
+            )));
+        }
+        Ok(())
+    }
+
+    fn new(slots: usize, dim: usize, storage: VectorStorage) -> Self {
+        let (floats, int8, binary) = columns(storage);
+        Self {
+            timestamps: Column::packed(slots, 1),
+            ingested_at: Column::packed(slots, 1),
+            vectors: floats.then(|| Column::aligned(slots, dim)),
+            int8: int8.then(|| Column::aligned(slots, dim)),
+            scales: int8.then(|| Column::packed(slots, 1)),
+            bits: binary.then(|| Column::aligned(slots, dim.div_ceil(64))),
+        }
+    }
+
+    fn write(&self, pos: usize, record: &VectorRecord, encoded: &Encoded) {
+        self.timestamps.write(pos, &[record.timestamp]);
+        self.ingested_at.write(pos, &[record.ingested_at]);
+        if let Some(vectors) = &self.vectors {
+            vectors.write(pos, &encoded.vector);
+        }
+        if let (Some(int8), Some(scales), Some(quantized)) = (&self.int8, &self.scales, &encoded.int8) {
+            int8.write(pos, &quantized.values);
+            scales.write(pos, &[quantized.scale]);
+        }
+        if let (Some(bits), Some(code)) = (&self.bits, &encoded.bits) {
+            bits.write(pos, &code.bits);
+        }
+    }
+
+    // The vector stored in slot `pos`: the float row, or the dequantized int8 code when the
+    // storage mode drops the floats.
+    fn vector(&self, pos: usize) -> Vec<f32> {
+        let mut vector = Vec::new();
+        if let Some(vectors) = &self.vectors {
+            vectors.read(pos, &mut vector);
+        } else if let (Some(int8), Some(scales)) = (&self.int8, &self.scales) {
+            let mut values = Vec::new();
+            int8.read(pos, &mut values);
+            let scale = scales.get(pos);
+            vector.extend(values.iter().map(|&value| value as f32 * scale));
+        }
+        vector
+    }
+
+    // Marks the slot empty. Its rows stay allocated and are overwritten when the slot is reused.
+    fn clear(&self, pos: usize) {
+        self.ingested_at.write(pos, &[0]);
+    }
+
+    // Scores slot `pos` with the cheapest representation the storage mode keeps. The
+    // kernels run on a copy in `row`, which is torn if a writer raced the copy.
+    fn score(&self, pos: usize, query: &Query, row: &mut Row) -> f32 {
+        if let (Some(query), Some(int8), Some(scales)) = (&query.int8, &self.int8, &self.scales) {
+            int8.read(pos, &mut row.int8);
+            return query.dot(&row.int8, scales.get(pos));
+        }
+        if let (Some(query), Some(bits)) = (&query.bits, &self.bits) {
+            bits.read(pos, &mut row.bits);
+            return query.similarity(&row.bits);
+        }
+        self.exact_score(pos, query, row).unwrap_or(0.0)
+    }
+
+    // `None` when the storage mode drops the float vectors.
+    fn exact_score(&self, pos: usize, query: &Query, row: &mut Row) -> Option<f32> {
+        let vectors = self.vectors.as_ref()?;
+        vectors.read(pos, &mut row.vector);
+        Some(query.metric.score(query.vector, &row.vector))
+    }
+}
+
+// Reusable buffers a scan copies arena rows into before scoring them.
+#[derive(Default)]
+struct Row {
+    vector: Vec<f32>,
+    int8: Vec<i8>,
+    bits: Vec<u64>,
+}
+
+// A search query in every representation the storage mode scores with.
+struct Query<'a> {
+    vector: &'a [f32],
+    metric: Metric,
+    int8: Option<QuantizedVector>,
+    bits: Option<BinaryCode>,
+}
+
+fn check_dim(expected: usize, vector: &[f32]) -> Result<()> {
+    if vector.len() != expected {
+        return Err(Error::DimensionMismatch { expected, actual: vector.len() });
+    }
+    Ok(())
+}
+
+fn check_truncate_dim(truncate_dim: Option<usize>, dim: usize) -> Result<()> {
+    match truncate_dim {
+        Some(truncate_dim) if truncate_dim > dim => Err(Error::InvalidConfig(format!(
+            "truncate_dim {} exceeds the {} dimensions of the vectors", truncate_dim, dim
+        ))),
+        _ => Ok(()),
+    }
+}
+
+// Quantized storage approximates angles, so it only supports the angular metrics.
+fn check_metric(storage: VectorStorage, metric: Metric) -> Result<()> {
+    if storage != VectorStorage::F32 && !matches!(metric, Metric::Cosine | Metric::Dot) {
+        return Err(Error::InvalidConfig(format!(
+            "{:?} storage supports the cosine and dot metrics, not {:?}",
+            storage, metric
+        )));
+    }
+    Ok(())
+}
+
+// A copy of `record` carrying `vector`, since stored records leave their vector in the arena.
+fn with_vector(record: &VectorRecord, vector: Vec<f32>) -> Arc<VectorRecord> {
+    Arc::new(VectorRecord { vector, ..record.clone() })
+}
+
+fn validate_vector(vector: &[f32]) -> Result<()> {
+    if vector.is_empty() {
+        return Err(Error::InvalidVector("vector is empty".to_string()));
+    }
+    if !vector.iter().all(|x| x.is_finite()) {
+        return Err(Error::InvalidVector("vector contains NaN or infinite values".to_string()));
     }
+    Ok(())
+}
+
+/// Optional constraints applied to every slot before it is scored.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchOptions<'a> {
+    pub filter: Option<&'a Filter>,
+    /// Inclusive lower bound on `VectorRecord::timestamp`.
+    pub since: Option<u64>,
+    /// Inclusive upper bound on `VectorRecord::timestamp`.
+    pub until: Option<u64>,
+    /// Candidates whose final score is worse than this threshold are pruned during the scan:
+    /// below it for similarity metrics, above it for distance metrics.
+    pub min_score: Option<f32>,
+    pub scoring: ScoringMode,
+    /// Copy each hit's stored vector into its record. Off by default, so hits share the
+    /// stored record and carry an empty vector.
+    pub with_vectors: bool,
+}
+
+impl<'a> SearchOptions<'a> {
+    pub fn with_filter(mut self, filter: &'a Filter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    pub fn with_time_range(mut self, since: Option<u64>, until: Option<u64>) -> Self {
+        self.since = since;
+        self.until = until;
+        self
+    }
+
+    pub fn with_min_score(mut self, min_score: f32) -> Self {
+        self.min_score = Some(min_score);
+        self
+    }
+
+    pub fn with_scoring(mut self, scoring: ScoringMode) -> Self {
+        self.scoring = scoring;
+        self
+    }
+
+    pub fn with_vectors(mut self) -> Self {
+        self.with_vectors = true;
+        self
+    }
+
+    fn in_time_range(&self, timestamp: u64) -> bool {
+        self.since.is_none_or(|since| timestamp >= since)
+            && self.until.is_none_or(|until| timestamp <= until)
+    }
+
+    fn matches(&self, record: &VectorRecord) -> bool {
+        self.filter.is_none_or(|filter| filter.matches(&record.attributes))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub record: Arc<VectorRecord>,
+    /// The final score results are ranked by.
+    pub score: f32,
+    /// The metric's raw value: a similarity, or a distance for smaller-is-better metrics.
+    pub similarity: f32,
+    /// Time-decay factor, present when a recency-weighted scoring mode is used.
+    pub recency: Option<f32>,
 }
 
 pub struct ShardedCircularBuffer {
     shards: Vec<Shard>,
     num_shards: usize,
+    max_age: Option<Duration>,
+    storage: VectorStorage,
+    metric: Metric,
+    // Declared with `with_dim` or set by the first insert; every vector of the buffer,
+    // stored or queried, has this length.
+    dim: OnceLock<usize>,
+    // Length vectors are cut to before they are stored or compared.
+    truncate_dim: Option<usize>,
 }
 
 impl ShardedCircularBuffer {
-    pub fn new(num_shards: usize, shard_size: usize) -> Self {
+    pub fn new(num_shards: usize, shard_size: usize) -> Result<Self> {
+        if num_shards == 0 || shard_size == 0 {
+            return Err(Error::InvalidConfig(format!(
+                "num_shards and shard_size must be positive (got {} x {})",
+                num_shards, shard_size
+            )));
+        }
+        Arena::check(shard_size, 1, VectorStorage::F32)?;
         let mut shards = Vec::with_capacity(num_shards);
         for _ in 0..num_shards {
             shards.push(Shard::new(shard_size));
         }
-        Self { shards, num_shards }
+        Ok(Self { shards, num_shards, max_age: None, storage: VectorStorage::F32, metric: Metric::Cosine, dim: OnceLock::new(), truncate_dim: None })
+    }
+
+    /// Forgets records held for longer than `max_age`, independently of how fast the ring wraps.
+    /// Age is measured from `VectorRecord::ingested_at`, so replayed events with an old
+    /// event time are still kept. Expired records are never returned by `search`.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// Sets how vectors are stored. Fails after the first insert, or if the storage mode
+    /// does not support the metric.
+    pub fn with_storage(mut self, storage: VectorStorage) -> Result<Self> {
+        self.check_empty("storage")?;
+        check_metric(storage, self.metric)?;
+        if let Some(&dim) = self.dim.get() {
+            self.check_arena(dim, storage)?;
+        }
+        self.storage = storage;
+        Ok(self)
+    }
+
+    pub fn storage(&self) -> VectorStorage {
+        self.storage
+    }
+
+    /// Sets how vectors are compared. With `Metric::Cosine` (the default) vectors are
+    /// normalized on insert, so it fails after the first insert. Also fails if the storage
+    /// mode does not support the metric.
+    pub fn with_metric(mut self, metric: Metric) -> Result<Self> {
+        self.check_empty("metric")?;
+        check_metric(self.storage, metric)?;
+        self.metric = metric;
+        Ok(self)
+    }
+
+    pub fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    /// Fixes the vector length up front instead of taking it from the first insert.
+    /// Fails if the length is already fixed.
+    pub fn with_dim(mut self, dim: usize) -> Result<Self> {
+        if dim == 0 {
+            return Err(Error::InvalidConfig("dim must be positive".to_string()));
+        }
+        if let Some(&fixed) = self.dim.get() {
+            return Err(Error::InvalidConfig(format!("dim is already fixed to {}", fixed)));
+        }
+        check_truncate_dim(self.truncate_dim, dim)?;
+        self.check_arena(dim, self.storage)?;
+        self.dim = OnceLock::from(dim);
+        Ok(self)
+    }
+
+    /// Keeps only the first `truncate_dim` dimensions of every inserted and queried vector,
+    /// re-normalized. Only meaningful for embeddings trained for it (Matryoshka
+    /// representation learning, e.g. nomic-embed, mxbai-embed, OpenAI text-embedding-3).
+    /// Vectors are still validated against the full `dim`. Fails after the first insert.
+    pub fn with_truncate_dim(mut self, truncate_dim: usize) -> Result<Self> {
+        if truncate_dim == 0 {
+            return Err(Error::InvalidConfig("truncate_dim must be positive".to_string()));
+        }
+        self.check_empty("truncate_dim")?;
+        if let Some(&dim) = self.dim.get() {
+            check_truncate_dim(Some(truncate_dim), dim)?;
+        }
+        self.truncate_dim = Some(truncate_dim);
+        Ok(self)
+    }
+
+    pub fn truncate_dim(&self) -> Option<usize> {
+        self.truncate_dim
+    }
+
+    /// Length of the stored vectors, once declared or set by the first insert.
+    pub fn dim(&self) -> Option<usize> {
+        self.dim.get().copied()
+    }
+
+    /// Total number of slots across all shards.
+    pub fn capacity(&self) -> usize {
+        self.shards.iter().map(|shard| shard.size).sum()
+    }
+
+    /// Stores `record`, overwriting the oldest slot of its shard.
+    pub fn insert(&self, record: VectorRecord) -> Result<()> {
+        let (shard, record, vectors) = self.prepare(record)?;
+        shard.insert(record, &vectors);
+        Ok(())
+    }
+
+    /// Returns the most recent live record stored under `id`, with its stored vector.
+    pub fn get(&self, id: &str) -> Option<Arc<VectorRecord>> {
+        let shard = &self.shards[self.get_shard_index(id)];
+        let (pos, record) = shard.find(id)?;
+        if self.expiry_cutoff().is_some_and(|cutoff| record.ingested_at < cutoff) {
+            return None;
+        }
+        let arena = shard.arena.get()?;
+        let ((current, vector), _) = shard.read(pos, || (shard.records[pos].load_full(), arena.vector(pos)));
+        // `None` if the slot was overwritten since `find`.
+        current.filter(|current| Arc::ptr_eq(current, &record))?;
+        Some(with_vector(&record, vector))
+    }
+
+    /// Removes every record stored under `id`. Returns `true` if anything was removed.
+    pub fn remove(&self, id: &str) -> bool {
+        let shard_idx = self.get_shard_index(id);
+        self.shards[shard_idx].remove(id) > 0
     }
 
-    pub fn insert(&self, record: VectorRecord) {
-        let shard_idx = self.get_shard_index(&record.id);
-        self.shards[shard_idx].insert(Arc::new(record));
+    /// Inserts `record`, replacing in place any record that already uses its id.
+    /// Concurrent upserts of one id are serialized, so exactly one copy survives.
+    /// A concurrent `insert` of the same id may still add a second copy.
+    pub fn upsert(&self, record: VectorRecord) -> Result<()> {
+        let (shard, record, vectors) = self.prepare(record)?;
+        shard.upsert(record, &vectors);
+        Ok(())
     }
 
-    pub fn search(&self, query_vector: &[f32], k: usize) -> Vec<(Arc<VectorRecord>, f32)> {
-        use crate::search::cosine_similarity;
+    /// Clears expired slots so later scans skip them. Their records are dropped, but the
+    /// shard keeps its vector capacity until the slots are reused.
+    /// Returns the number of evicted records. A no-op when no max age is configured.
+    pub fn evict_expired(&self) -> usize {
+        use rayon::prelude::*;
+
+        match self.expiry_cutoff() {
+            Some(cutoff) => self.shards
+                .par_iter()
+                .map(|shard| shard.evict_older_than(cutoff))
+                .sum(),
+            None => 0,
+        }
+    }
+
+    /// Spawns a background thread that calls `evict_expired` every `interval`.
+    /// The thread only holds a weak reference and exits once the buffer is dropped.
+    pub fn spawn_sweeper(self: &Arc<Self>, interval: Duration) -> thread::JoinHandle<()> {
+        let weak: Weak<Self> = Arc::downgrade(self);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            match weak.upgrade() {
+                Some(buffer) => {
+                    buffer.evict_expired();
+                }
+                None => break,
+            }
+        })
+    }
+
+    pub fn search(&self, query_vector: &[f32], k: usize) -> Result<Vec<(Arc<VectorRecord>, f32)>> {
+        self.search_with(query_vector, k, &SearchOptions::default())
+    }
+
+    /// Ranks only records whose timestamp lies within `[since, until]`.
+    pub fn search_time_range(
+        &self,
+        query_vector: &[f32],
+        k: usize,
+        since: Option<u64>,
+        until: Option<u64>,
+    ) -> Result<Vec<(Arc<VectorRecord>, f32)>> {
+        self.search_with(query_vector, k, &SearchOptions::default().with_time_range(since, until))
+    }
+
+    /// Returns every record scoring at least `min_score`, best first, up to `max_results`.
+    pub fn search_range(
+        &self,
+        query_vector: &[f32],
+        min_score: f32,
+        max_results: usize,
+    ) -> Result<Vec<(Arc<VectorRecord>, f32)>> {
+        self.search_with(query_vector, max_results, &SearchOptions::default().with_min_score(min_score))
+    }
+
+    pub fn search_with(
+        &self,
+        query_vector: &[f32],
+        k: usize,
+        options: &SearchOptions,
+    ) -> Result<Vec<(Arc<VectorRecord>, f32)>> {
+        let hits = self.search_scored(query_vector, k, options)?;
+        Ok(hits.into_iter().map(|hit| (hit.record, hit.score)).collect())
+    }
+
+    /// Like `search_with`, but returns how each hit's final score was composed.
+    pub fn search_scored(&self, query_vector: &[f32], k: usize, options: &SearchOptions) -> Result<Vec<SearchHit>> {
         use rayon::prelude::*;
         use std::collections::BinaryHeap;
         use std::cmp::Ordering;
 
-        #[derive(Clone)]
+        validate_vector(query_vector)?;
+        // Nothing is stored before the dimension is known, so any query is fine then.
+        if let Some(&expected) = self.dim.get() {
+            check_dim(expected, query_vector)?;
+        }
+        check_truncate_dim(self.truncate_dim, query_vector.len())?;
+        if !self.metric.higher_is_better() && options.scoring != ScoringMode::Similarity {
+            return Err(Error::InvalidConfig(format!(
+                "recency-weighted scoring needs a similarity metric, not {:?}",
+                self.metric
+            )));
+        }
+        let mut shaped = query_vector.to_vec();
+        self.shape(&mut shaped);
+        let query_vector = shaped.as_slice();
+        let metric = self.metric;
+
+        // A candidate and the slot it was read from, so re-ranking can find its vector.
         struct SearchResult {
-            record: Arc<VectorRecord>,
-            score: f32,
+            hit: SearchHit,
+            // The score mapped so that larger is better, whatever the metric.
+            key: f32,
+            shard: usize,
+            pos: usize,
+            // The slot's sequence when it was scanned; re-ranking drops the candidate if the
+            // slot was written since.
+            seq: u64,
+            // Copied when the candidate enters the heap if the caller asked for vectors.
+            vector: Option<Vec<f32>>,
+        }
+
+        enum Scanned {
+            Skipped,
+            Expired,
+            Candidate(SearchHit, f32, Option<Vec<f32>>),
         }
 
         impl PartialEq for SearchResult {
             fn eq(&self, other: &Self) -> bool {
-                self.score == other.score
+                self.key == other.key
             }
         }
 
@@ -72,61 +857,225 @@ impl ShardedCircularBuffer {
 
         impl PartialOrd for SearchResult {
             fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-                other.score.partial_cmp(&self.score)
+                Some(self.cmp(other))
             }
         }
 
         impl Ord for SearchResult {
             fn cmp(&self, other: &Self) -> Ordering {
-                self.partial_cmp(other).unwrap_or(Ordering::Equal)
+                other.key.partial_cmp(&self.key).unwrap_or(Ordering::Equal)
             }
         }
 
+        // `k` may be "unbounded" (e.g. `usize::MAX` for range searches).
+        let k = k.min(self.capacity());
+        let cutoff = self.expiry_cutoff();
+        let now = now_timestamp();
+
+        let query = Query {
+            vector: query_vector,
+            metric,
+            int8: matches!(self.storage, VectorStorage::Int8 { .. }).then(|| QuantizedVector::quantize(query_vector)),
+            bits: matches!(self.storage, VectorStorage::Binary { .. }).then(|| BinaryCode::from_vector(query_vector)),
+        };
+        let rerank = match self.storage {
+            VectorStorage::Int8 { rerank: Some(factor) } | VectorStorage::Binary { oversample: factor } => {
+                Some(factor.max(1))
+            }
+            _ => None,
+        };
+        // When re-ranking, the scan keeps more candidates and leaves the `min_score`
+        // check to the exact scores, since the scan's scores are approximate.
+        let candidates = rerank.map_or(k, |factor| k.saturating_mul(factor).min(self.capacity()));
+        let min_score = if rerank.is_some() { None } else { options.min_score };
+        let hit = |record: Arc<VectorRecord>, similarity: f32| {
+            let breakdown = options.scoring.score(similarity, now.saturating_sub(record.timestamp));
+            SearchHit {
+                record,
+                score: breakdown.score,
+                similarity: breakdown.similarity,
+                recency: breakdown.recency,
+            }
+        };
+
         let heaps: Vec<BinaryHeap<SearchResult>> = self.shards
             .par_iter()
-            .map(|shard| {
-                let mut heap: BinaryHeap<SearchResult> = BinaryHeap::with_capacity(k + 1);
-                for slot in &shard.buffer {
-                    let guard = slot.load();
-                    if let Some(record) = &*guard {
-                        let score = cosine_similarity(query_vector, &record.vector);
-                        let should_push = if heap.len() < k {
-                            true
-                        } else if let Some(min_res) = heap.peek() {
-                            score > min_res.score
-                        } else {
-                            true
+            .enumerate()
+            .map(|(shard_idx, shard)| {
+                let mut heap: BinaryHeap<SearchResult> = BinaryHeap::with_capacity(candidates.min(shard.size) + 1);
+                let Some(arena) = shard.arena.get() else { return heap };
+                let mut row = Row::default();
+                for pos in 0..shard.size {
+                    // Without a filter, records are only loaded for candidates good enough
+                    // to enter the heap.
+                    let worst = if heap.len() < candidates { None } else { heap.peek().map(|worst| worst.key) };
+                    let (scanned, seq) = shard.read(pos, || {
+                        let ingested_at = arena.ingested_at.get(pos);
+                        if ingested_at == 0 {
+                            return Scanned::Skipped;
+                        }
+                        if cutoff.is_some_and(|cutoff| ingested_at < cutoff) {
+                            return Scanned::Expired;
+                        }
+                        let timestamp = arena.timestamps.get(pos);
+                        if !options.in_time_range(timestamp) {
+                            return Scanned::Skipped;
+                        }
+                        // A filter needs the record, so it is loaded up front and rows the
+                        // filter rejects are never scored.
+                        let filtered = match options.filter {
+                            Some(_) => match shard.records[pos].load_full() {
+                                Some(record) if options.matches(&record) => Some(record),
+                                _ => return Scanned::Skipped,
+                            },
+                            None => None,
                         };
-
-                        if should_push {
-                            let record_arc = Arc::clone(record);
-                            heap.push(SearchResult { record: record_arc, score });
-                            if heap.len() > k {
+                        let similarity = arena.score(pos, &query, &mut row);
+                        let breakdown = options.scoring.score(similarity, now.saturating_sub(timestamp));
+                        if min_score.is_some_and(|min_score| !metric.passes(breakdown.score, min_score)) {
+                            return Scanned::Skipped;
+                        }
+                        let key = metric.rank_key(breakdown.score);
+                        if worst.is_some_and(|worst| key <= worst) {
+                            return Scanned::Skipped;
+                        }
+                        match filtered.or_else(|| shard.records[pos].load_full()) {
+                            Some(record) => Scanned::Candidate(
+                                SearchHit {
+                                    record,
+                                    score: breakdown.score,
+                                    similarity: breakdown.similarity,
+                                    recency: breakdown.recency,
+                                },
+                                key,
+                                options.with_vectors.then(|| arena.vector(pos)),
+                            ),
+                            _ => Scanned::Skipped,
+                        }
+                    });
+                    match scanned {
+                        Scanned::Candidate(hit, key, vector) => {
+                            heap.push(SearchResult { hit, key, shard: shard_idx, pos, seq, vector });
+                            if heap.len() > candidates {
                                 heap.pop();
                             }
                         }
+                        Scanned::Expired => {
+                            // Lazy sweep: release the expired record while we are here.
+                            shard.clear_if_unchanged(pos, seq);
+                        }
+                        Scanned::Skipped => {}
                     }
                 }
                 heap
             })
             .collect();
 
-        let mut final_heap = BinaryHeap::with_capacity(k + 1);
+        let mut final_heap = BinaryHeap::with_capacity(candidates + 1);
         for heap in heaps {
             for result in heap {
                 final_heap.push(result);
-                if final_heap.len() > k {
+                if final_heap.len() > candidates {
                     final_heap.pop();
                 }
             }
         }
 
-        let mut results: Vec<_> = final_heap.into_iter()
-            .map(|res| (res.record, res.score))
-            .collect();
+        let mut results: Vec<(SearchHit, Option<Vec<f32>>)> = if rerank.is_some() {
+            let mut row = Row::default();
+            final_heap.into_iter()
+                .filter_map(|candidate| {
+                    // Candidates whose slot was overwritten since the scan are dropped.
+                    let shard = &self.shards[candidate.shard];
+                    let arena = shard.arena.get()?;
+                    let (similarity, seq) = shard.read(candidate.pos, || {
+                        arena.exact_score(candidate.pos, &query, &mut row)
+                    });
+                    if seq != candidate.seq {
+                        return None;
+                    }
+                    Some((hit(candidate.hit.record, similarity?), candidate.vector))
+                })
+                .filter(|(hit, _)| options.min_score.is_none_or(|min_score| metric.passes(hit.score, min_score)))
+                .collect()
+        } else {
+            final_heap.into_iter().map(|res| (res.hit, res.vector)).collect()
+        };
+        results.sort_by(|(a, _), (b, _)| {
+            metric.rank_key(b.score).partial_cmp(&metric.rank_key(a.score)).unwrap_or(Ordering::Equal)
+        });
+        results.truncate(k);
+        Ok(results.into_iter()
+            .map(|(hit, vector)| match vector {
+                Some(vector) => SearchHit { record: with_vector(&hit.record, vector), ..hit },
+                None => hit,
+            })
+            .collect())
+    }
 
-        results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
-        results
+    // Validates `record`, stamps its ingest time and splits its vector off into the
+    // representations the storage mode keeps.
+    fn prepare(&self, mut record: VectorRecord) -> Result<(&Shard, Arc<VectorRecord>, Encoded)> {
+        validate_vector(&record.vector)?;
+        check_truncate_dim(self.truncate_dim, record.vector.len())?;
+        match self.dim.get() {
+            Some(&dim) => check_dim(dim, &record.vector)?,
+            None => self.check_arena(record.vector.len(), self.storage)?,
+        }
+        // Fixed only once the record passed every check, so a rejected first insert leaves
+        // the dimension open. A concurrent first insert may have fixed another one meanwhile.
+        let dim = *self.dim.get_or_init(|| record.vector.len());
+        check_dim(dim, &record.vector)?;
+        // The max age counts from here, not from when the record was built, which may be
+        // long before if it waited on a slow embed.
+        record.ingested_at = now_timestamp();
+
+        let shard = &self.shards[self.get_shard_index(&record.id)];
+        shard.arena.get_or_init(|| Arena::new(shard.size, self.truncate_dim.unwrap_or(dim), self.storage));
+        let mut vector = std::mem::take(&mut record.vector);
+        self.shape(&mut vector);
+        let vectors = match self.storage {
+            VectorStorage::F32 => Encoded { vector, int8: None, bits: None },
+            VectorStorage::Int8 { rerank } => Encoded {
+                int8: Some(QuantizedVector::quantize(&vector)),
+                vector: if rerank.is_some() { vector } else { Vec::new() },
+                bits: None,
+            },
+            VectorStorage::Binary { .. } => Encoded { bits: Some(BinaryCode::from_vector(&vector)), vector, int8: None },
+        };
+        Ok((shard, Arc::new(record), vectors))
+    }
+
+    // Fails if the shards could not allocate arenas for `dim`-long vectors.
+    fn check_arena(&self, dim: usize, storage: VectorStorage) -> Result<()> {
+        Arena::check(self.shards[0].size, self.truncate_dim.unwrap_or(dim), storage)
+    }
+
+    // Settings that shape the stored vectors cannot change once a shard holds any.
+    fn check_empty(&self, setting: &str) -> Result<()> {
+        if self.shards.iter().any(|shard| shard.arena.get().is_some()) {
+            return Err(Error::InvalidConfig(format!("{} must be set before the first insert", setting)));
+        }
+        Ok(())
+    }
+
+    // Cuts `vector` to `truncate_dim` and normalizes it when the metric expects it.
+    // Truncated vectors are always re-normalized, since the cut changes their norm.
+    fn shape(&self, vector: &mut Vec<f32>) {
+        if let Some(truncate_dim) = self.truncate_dim {
+            vector.truncate(truncate_dim);
+            normalize(vector);
+        } else if self.metric == Metric::Cosine {
+            normalize(vector);
+        }
+    }
+
+    // Records ingested strictly before the cutoff are expired.
+    fn expiry_cutoff(&self) -> Option<u64> {
+        self.max_age.map(|max_age| {
+            // Ages beyond u64 nanoseconds (~584 years) keep everything.
+            now_timestamp().saturating_sub(u64::try_from(max_age.as_nanos()).unwrap_or(u64::MAX))
+        })
     }
 
     fn get_shard_index(&self, id: &str) -> usize {
@@ -136,3 +1085,469 @@ impl ShardedCircularBuffer {
         (hasher.finish() as usize) % self.num_shards
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::models::NANOS_PER_SEC;
+
+    fn record(id: &str, age_secs: u64) -> VectorRecord {
+        let mut record = VectorRecord::new(id.to_string(), vec![1.0, 0.0], id.to_string());
+        record.timestamp -= age_secs * NANOS_PER_SEC;
+        record
+    }
+
+    // Moves the stored ingest time of `id` back by `secs`, in the record and the arena
+    // column the search scan reads.
+    fn age<'a>(buffer: &'a ShardedCircularBuffer, id: &str, secs: u64) -> (&'a Shard, usize) {
+        let shard = &buffer.shards[buffer.get_shard_index(id)];
+        let (pos, record) = shard.find(id).unwrap();
+        let mut aged = (*record).clone();
+        aged.ingested_at -= secs * NANOS_PER_SEC;
+        let arena = shard.arena.get().unwrap();
+        arena.ingested_at.write(pos, &[aged.ingested_at]);
+        shard.records[pos].store(Some(Arc::new(aged)));
+        (shard, pos)
+    }
+
+    #[test]
+    fn test_max_age_eviction() {
+        let buffer = ShardedCircularBuffer::new(2, 8).unwrap().with_max_age(Duration::from_secs(60));
+
+        buffer.insert(record("fresh", 0)).unwrap();
+        // Built two minutes ago, but the window starts when the record is inserted.
+        buffer.insert(record("built_early", 120)).unwrap();
+        buffer.insert(record("swept", 0)).unwrap();
+        assert_eq!(buffer.search(&[1.0, 0.0], 10).unwrap().len(), 3);
+
+        // Age records past the window after they have been stored.
+        age(&buffer, "fresh", 120);
+        assert_eq!(buffer.evict_expired(), 1);
+        assert!(buffer.get("fresh").is_none());
+
+        // A search skips an expired slot and clears it on the way.
+        let (shard, pos) = age(&buffer, "swept", 120);
+        let results = buffer.search(&[1.0, 0.0], 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0.id, "built_early");
+        assert!(shard.records[pos].load().is_none());
+        assert_eq!(shard.arena.get().unwrap().ingested_at.get(pos), 0);
+        assert_eq!(buffer.evict_expired(), 0);
+    }
+
+    #[test]
+    fn test_huge_max_age_keeps_records() {
+        let buffer = ShardedCircularBuffer::new(1, 4).unwrap().with_max_age(Duration::MAX);
+        buffer.insert(record("kept", 0)).unwrap();
+        assert_eq!(buffer.evict_expired(), 0);
+        assert_eq!(buffer.search(&[1.0, 0.0], 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_get_remove_upsert() {
+        let buffer = ShardedCircularBuffer::new(4, 8).unwrap();
+        buffer.insert(VectorRecord::new("UAL123".into(), vec![1.0, 0.0], "climbing".into())).unwrap();
+        buffer.insert(VectorRecord::new("DAL456".into(), vec![0.0, 1.0], "cruising".into())).unwrap();
+
+        assert_eq!(buffer.get("UAL123").unwrap().metadata, "climbing");
+        assert!(buffer.get("missing").is_none());
+
+        buffer.upsert(VectorRecord::new("UAL123".into(), vec![1.0, 0.0], "descending".into())).unwrap();
+        let results = buffer.search(&[1.0, 0.0], 10).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0.metadata, "descending");
+
+        assert!(buffer.remove("UAL123"));
+        assert!(!buffer.remove("UAL123"));
+        assert!(buffer.get("UAL123").is_none());
+        assert_eq!(buffer.search(&[1.0, 0.0], 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_upsert_replaces_every_copy() {
+        let buffer = ShardedCircularBuffer::new(2, 64).unwrap().with_max_age(Duration::from_secs(60));
+        buffer.insert(record("UAL123", 0)).unwrap();
+        // Built before the window, e.g. after a slow embed: it still replaces the old copy.
+        let mut late = record("UAL123", 120);
+        late.metadata = "late".into();
+        buffer.upsert(late).unwrap();
+        let results = buffer.search(&[1.0, 0.0], 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0.metadata, "late");
+
+        // Upserts of a new id race to the fallback insert; only one may win. A large shard
+        // keeps each upsert's scan long enough for the race to show up.
+        let buffer = Arc::new(ShardedCircularBuffer::new(1, 8192).unwrap());
+        let barrier = Arc::new(std::sync::Barrier::new(4));
+        let upserters: Vec<_> = (0..4)
+            .map(|_| {
+                let buffer = Arc::clone(&buffer);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    for i in 0..100 {
+                        barrier.wait();
+                        buffer.upsert(VectorRecord::new(format!("id{}", i), vec![0.0, 1.0], String::new())).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for upserter in upserters {
+            upserter.join().unwrap();
+        }
+        for i in 0..100 {
+            let id = format!("id{}", i);
+            let copies = buffer.search(&[0.0, 1.0], 1000).unwrap().into_iter().filter(|(r, _)| r.id == id).count();
+            assert_eq!(copies, 1, "{} has {} copies", id, copies);
+        }
+    }
+
+    #[test]
+    fn test_search_with_filter() {
+        use crate::models::Attributes;
+
+        let buffer = ShardedCircularBuffer::new(4, 8).unwrap();
+        for (id, host, severity) in [("a", "db-1", 1), ("b", "db-1", 5), ("c", "web-1", 5)] {
+            let mut attributes = Attributes::new();
+            attributes.insert("host".into(), host.into());
+            attributes.insert("severity".into(), severity.into());
+            let record = VectorRecord::new(id.into(), vec![1.0, 0.0], id.into())
+                .with_attributes(attributes);
+            buffer.insert(record).unwrap();
+        }
+
+        let filter = Filter::eq("host", "db-1").and(Filter::Gte("severity".into(), 3.into()));
+        let results = buffer.search_with(&[1.0, 0.0], 10, &SearchOptions::default().with_filter(&filter)).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0.id, "b");
+    }
+
+    #[test]
+    fn test_search_time_range() {
+        let buffer = ShardedCircularBuffer::new(4, 8).unwrap();
+        buffer.insert(record("now", 0)).unwrap();
+        buffer.insert(record("minute_ago", 60)).unwrap();
+        buffer.insert(record("hour_ago", 3600)).unwrap();
+
+        let now = now_timestamp();
+        let secs = |s: u64| s * NANOS_PER_SEC;
+        let results = buffer.search_time_range(&[1.0, 0.0], 10, Some(now - secs(90)), None).unwrap();
+        assert_eq!(results.len(), 2);
+
+        let results = buffer.search_time_range(&[1.0, 0.0], 10, Some(now - secs(7200)), Some(now - secs(30))).unwrap();
+        let mut ids: Vec<_> = results.iter().map(|(r, _)| r.id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, ["hour_ago", "minute_ago"]);
+    }
+
+    #[test]
+    fn test_search_range() {
+        let buffer = ShardedCircularBuffer::new(4, 8).unwrap();
+        for (id, vector) in [("same", [1.0, 0.0]), ("close", [0.8, 0.6]), ("far", [0.0, 1.0])] {
+            buffer.insert(VectorRecord::new(id.into(), vector.to_vec(), id.into())).unwrap();
+        }
+
+        let results = buffer.search_range(&[1.0, 0.0], 0.5, usize::MAX).unwrap();
+        let ids: Vec<_> = results.iter().map(|(r, _)| r.id.as_str()).collect();
+        assert_eq!(ids, ["same", "close"]);
+
+        assert_eq!(buffer.search_range(&[1.0, 0.0], 0.5, 1).unwrap().len(), 1);
+        assert!(buffer.search_range(&[1.0, 0.0], 1.5, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_recency_weighted_search() {
+        let buffer = ShardedCircularBuffer::new(4, 8).unwrap();
+        let mut old = record("old_exact", 1200);
+        old.vector = vec![1.0, 0.0];
+        let mut fresh = record("fresh_close", 0);
+        fresh.vector = vec![0.8, 0.6];
+        buffer.insert(old).unwrap();
+        buffer.insert(fresh).unwrap();
+
+        let results = buffer.search(&[1.0, 0.0], 2).unwrap();
+        assert_eq!(results[0].0.id, "old_exact");
+
+        let scoring = ScoringMode::recency_weighted(Duration::from_secs(300), 0.5);
+        let hits = buffer.search_scored(&[1.0, 0.0], 2, &SearchOptions::default().with_scoring(scoring)).unwrap();
+        assert_eq!(hits[0].record.id, "fresh_close");
+        assert!((hits[0].similarity - 0.8).abs() < 1e-6);
+        assert!(hits[0].recency.unwrap() > hits[1].recency.unwrap());
+    }
+
+    // Pseudo-random unit vectors, so the test needs no extra dependencies.
+    fn random_unit_vectors(count: usize, dim: usize, mut seed: u64) -> Vec<Vec<f32>> {
+        (0..count)
+            .map(|_| {
+                let mut vector: Vec<f32> = (0..dim)
+                    .map(|_| {
+                        seed ^= seed << 13;
+                        seed ^= seed >> 7;
+                        seed ^= seed << 17;
+                        (seed >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+                    })
+                    .collect();
+                let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+                vector.iter_mut().for_each(|x| *x /= norm);
+                vector
+            })
+            .collect()
+    }
+
+    // Average fraction of the exact top-k that `buffer` also returns.
+    fn recall_at(buffer: &ShardedCircularBuffer, exact: &ShardedCircularBuffer, queries: &[Vec<f32>], k: usize) -> f32 {
+        let mut found = 0;
+        for query in queries {
+            let expected: Vec<String> = exact.search(query, k).unwrap().into_iter().map(|(r, _)| r.id.clone()).collect();
+            found += buffer.search(query, k).unwrap().iter().filter(|(r, _)| expected.contains(&r.id)).count();
+        }
+        found as f32 / (queries.len() * k) as f32
+    }
+
+    #[test]
+    fn test_int8_storage_recall() {
+        let exact = ShardedCircularBuffer::new(4, 512).unwrap();
+        let int8 = ShardedCircularBuffer::new(4, 512).unwrap()
+            .with_storage(VectorStorage::Int8 { rerank: None }).unwrap();
+        let reranked = ShardedCircularBuffer::new(4, 512).unwrap()
+            .with_storage(VectorStorage::Int8 { rerank: Some(4) }).unwrap();
+        for (i, vector) in random_unit_vectors(1000, 64, 42).into_iter().enumerate() {
+            for buffer in [&exact, &int8, &reranked] {
+                buffer.insert(VectorRecord::new(format!("r{}", i), vector.clone(), String::new())).unwrap();
+            }
+        }
+        assert!(int8.shards.iter().filter_map(|shard| shard.arena.get()).all(|arena| arena.vectors.is_none()));
+
+        let queries = random_unit_vectors(20, 64, 7);
+        let int8_recall = recall_at(&int8, &exact, &queries, 10);
+        let reranked_recall = recall_at(&reranked, &exact, &queries, 10);
+        assert!(int8_recall >= 0.9, "int8 recall@10 was {}", int8_recall);
+        assert!(reranked_recall >= 0.99, "re-ranked recall@10 was {}", reranked_recall);
+
+        // Re-ranked scores are exact.
+        assert_eq!(reranked.search(&queries[0], 1).unwrap()[0].1, exact.search(&queries[0], 1).unwrap()[0].1);
+    }
+
+    #[test]
+    fn test_binary_storage_recall() {
+        let exact = ShardedCircularBuffer::new(4, 512).unwrap();
+        let oversampled = ShardedCircularBuffer::new(4, 512).unwrap()
+            .with_storage(VectorStorage::Binary { oversample: 10 }).unwrap();
+        let single = ShardedCircularBuffer::new(4, 512).unwrap()
+            .with_storage(VectorStorage::Binary { oversample: 1 }).unwrap();
+        for (i, vector) in random_unit_vectors(1000, 384, 42).into_iter().enumerate() {
+            for buffer in [&exact, &oversampled, &single] {
+                buffer.insert(VectorRecord::new(format!("r{}", i), vector.clone(), String::new())).unwrap();
+            }
+        }
+
+        let queries = random_unit_vectors(20, 384, 7);
+        let recall = recall_at(&oversampled, &exact, &queries, 10);
+        let single_recall = recall_at(&single, &exact, &queries, 10);
+        // Uniform random vectors are the worst case for sign bits: every neighbor is close
+        // to orthogonal. Real embeddings cluster and fare better.
+        assert!(recall >= 0.8, "binary recall@10 with 10x oversampling was {}", recall);
+        assert!(recall > single_recall, "oversampling did not help: {} vs {}", recall, single_recall);
+
+        // Candidates are re-scored exactly, and `min_score` applies to the exact score.
+        let expected = exact.search(&queries[0], 1).unwrap();
+        let results = oversampled.search_range(&queries[0], expected[0].1, 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].1, expected[0].1);
+    }
+
+    #[test]
+    fn test_arena_rows_are_aligned() {
+        let buffer = ShardedCircularBuffer::new(1, 4).unwrap();
+        buffer.insert(VectorRecord::new("a".into(), vec![0.6, 0.8, 0.0], "a".into())).unwrap();
+        assert_eq!(buffer.dim(), Some(3));
+
+        let vectors = buffer.shards[0].arena.get().unwrap().vectors.as_ref().unwrap();
+        assert_eq!(vectors.stride, ROW_ALIGN / 4);
+        let mut row = Vec::new();
+        vectors.read(0, &mut row);
+        assert_eq!(row, [0.6, 0.8, 0.0]);
+        assert_eq!(vectors.cells(1).as_ptr() as usize % ROW_ALIGN, 0);
+    }
+
+    #[test]
+    fn test_get_returns_stored_vector() {
+        let buffer = ShardedCircularBuffer::new(2, 4).unwrap();
+        buffer.insert(VectorRecord::new("a".into(), vec![0.6, 0.8, 0.0], "a".into())).unwrap();
+        assert_eq!(buffer.get("a").unwrap().vector, [0.6, 0.8, 0.0]);
+        let with_vectors = SearchOptions::default().with_vectors();
+        assert_eq!(buffer.search_with(&[1.0, 0.0, 0.0], 1, &with_vectors).unwrap()[0].0.vector, [0.6, 0.8, 0.0]);
+        // Without asking for vectors, hits share the stored record.
+        let (record, _) = buffer.search(&[1.0, 0.0, 0.0], 1).unwrap().remove(0);
+        assert!(record.vector.is_empty());
+        let shard = &buffer.shards[buffer.get_shard_index("a")];
+        assert!(Arc::ptr_eq(&record, &shard.find("a").unwrap().1));
+
+        // Without float vectors, the int8 code is dequantized.
+        let int8 = ShardedCircularBuffer::new(2, 4).unwrap().with_storage(VectorStorage::Int8 { rerank: None }).unwrap();
+        int8.insert(VectorRecord::new("a".into(), vec![0.6, 0.8, 0.0], "a".into())).unwrap();
+        let vector = &int8.get("a").unwrap().vector;
+        assert_eq!(vector.len(), 3);
+        assert!(vector.iter().zip([0.6, 0.8, 0.0]).all(|(x, y)| (x - y).abs() < 0.01));
+    }
+
+    #[test]
+    fn test_concurrent_writers_and_readers() {
+        // Every record's vector is a unit vector whose direction encodes its id, so a
+        // torn read would show up as a score that doesn't match the returned record.
+        // The dot metric stores vectors as given, so scores can be compared exactly.
+        let buffer = Arc::new(ShardedCircularBuffer::new(2, 8).unwrap().with_metric(Metric::Dot).unwrap());
+        let vector = |i: usize| {
+            let angle = i as f32 * 0.001;
+            vec![angle.cos(), angle.sin(), 0.0, 0.0]
+        };
+
+        let writers: Vec<_> = (0..4)
+            .map(|w| {
+                let buffer = Arc::clone(&buffer);
+                thread::spawn(move || {
+                    for i in 0..2000 {
+                        let i = w * 2000 + i;
+                        buffer.insert(VectorRecord::new(i.to_string(), vector(i), String::new())).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let query = [1.0, 0.0, 0.0, 0.0];
+        while !writers.iter().all(|writer| writer.is_finished()) {
+            for (record, score) in buffer.search(&query, 16).unwrap() {
+                let expected = crate::search::dot_product(&query, &vector(record.id.parse().unwrap()));
+                assert_eq!(score, expected, "torn read for record {}", record.id);
+            }
+        }
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(buffer.search(&query, 100).unwrap().len(), 16);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let records = [("short", vec![0.1, 0.0]), ("long", vec![10.0, 1.0]), ("far", vec![-5.0, 0.0])];
+        let buffer_with = |metric: Metric| {
+            let buffer = ShardedCircularBuffer::new(2, 8).unwrap().with_metric(metric).unwrap();
+            for (id, vector) in &records {
+                buffer.insert(VectorRecord::new(id.to_string(), vector.clone(), id.to_string())).unwrap();
+            }
+            buffer
+        };
+        let ranking = |buffer: &ShardedCircularBuffer, query: &[f32]| -> Vec<String> {
+            buffer.search(query, 3).unwrap().into_iter().map(|(r, _)| r.id.clone()).collect()
+        };
+
+        // Cosine ignores the norm, unlike the inner product.
+        let cosine = buffer_with(Metric::Cosine);
+        assert_eq!(ranking(&cosine, &[2.0, 0.0]), ["short", "long", "far"]);
+        assert!((cosine.search(&[2.0, 0.0], 1).unwrap()[0].1 - 1.0).abs() < 1e-6);
+        assert_eq!(ranking(&buffer_with(Metric::Dot), &[2.0, 0.0]), ["long", "short", "far"]);
+
+        // Distances rank the smallest first.
+        let l2 = buffer_with(Metric::SquaredL2);
+        assert_eq!(ranking(&l2, &[0.0, 0.0]), ["short", "far", "long"]);
+        assert!((l2.search(&[0.0, 0.0], 1).unwrap()[0].1 - 0.01).abs() < 1e-6);
+        let within: Vec<_> = l2.search_range(&[0.0, 0.0], 30.0, 10).unwrap().into_iter().map(|(r, _)| r.id.clone()).collect();
+        assert_eq!(within, ["short", "far"]);
+        assert_eq!(ranking(&buffer_with(Metric::Manhattan), &[9.0, 0.0]), ["long", "short", "far"]);
+
+        let scoring = ScoringMode::recency_weighted(Duration::from_secs(60), 0.5);
+        let options = SearchOptions::default().with_scoring(scoring);
+        assert!(matches!(l2.search_scored(&[0.0, 0.0], 1, &options), Err(Error::InvalidConfig(_))));
+
+        // Quantized storage rejects distance metrics up front, in either builder order.
+        let int8 = ShardedCircularBuffer::new(2, 8).unwrap().with_storage(VectorStorage::Int8 { rerank: None }).unwrap();
+        assert!(matches!(int8.with_metric(Metric::SquaredL2), Err(Error::InvalidConfig(_))));
+        let l2 = ShardedCircularBuffer::new(2, 8).unwrap().with_metric(Metric::Manhattan).unwrap();
+        assert!(matches!(l2.with_storage(VectorStorage::Binary { oversample: 4 }), Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_truncate_dim() {
+        let buffer = ShardedCircularBuffer::new(1, 4).unwrap().with_metric(Metric::Dot).unwrap().with_truncate_dim(2).unwrap();
+        buffer.insert(VectorRecord::new("a".into(), vec![3.0, 4.0, 12.0], "a".into())).unwrap();
+        // Validated against the full length, stored at the truncated one.
+        assert_eq!(buffer.dim(), Some(3));
+        let vectors = buffer.shards[0].arena.get().unwrap().vectors.as_ref().unwrap();
+        assert_eq!(vectors.width, 2);
+
+        // Both sides are cut to [3, 4] and re-normalized, even with the dot metric.
+        let results = buffer.search(&[6.0, 8.0, -1.0], 1).unwrap();
+        assert!((results[0].1 - 1.0).abs() < 1e-6);
+        assert!(matches!(buffer.search(&[1.0, 0.0], 1), Err(Error::DimensionMismatch { expected: 3, actual: 2 })));
+
+        // Longer than the vectors.
+        let declared = ShardedCircularBuffer::new(1, 4).unwrap().with_dim(384).unwrap();
+        assert!(matches!(declared.with_truncate_dim(512), Err(Error::InvalidConfig(_))));
+        let truncated = ShardedCircularBuffer::new(1, 4).unwrap().with_truncate_dim(512).unwrap();
+        assert!(matches!(truncated.with_dim(384), Err(Error::InvalidConfig(_))));
+        let inferred = ShardedCircularBuffer::new(1, 4).unwrap().with_truncate_dim(3).unwrap();
+        let short = VectorRecord::new("a".into(), vec![1.0, 0.0], "a".into());
+        assert!(matches!(inferred.insert(short), Err(Error::InvalidConfig(_))));
+        assert_eq!(inferred.dim(), None);
+        assert!(matches!(ShardedCircularBuffer::new(1, 4).unwrap().with_truncate_dim(0), Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        assert!(matches!(ShardedCircularBuffer::new(0, 8), Err(Error::InvalidConfig(_))));
+
+        let buffer = ShardedCircularBuffer::new(4, 8).unwrap();
+        let nan = VectorRecord::new("nan".into(), vec![f32::NAN, 0.0], "nan".into());
+        assert!(matches!(buffer.insert(nan), Err(Error::InvalidVector(_))));
+        assert!(matches!(buffer.search(&[], 5), Err(Error::InvalidVector(_))));
+    }
+
+    #[test]
+    fn test_dimension_checks() {
+        // Inferred from the first insert that is stored; rejected ones leave it open.
+        let buffer = ShardedCircularBuffer::new(2, 4).unwrap().with_truncate_dim(3).unwrap();
+        assert_eq!(buffer.dim(), None);
+        assert!(buffer.search(&[1.0, 0.0, 0.0], 5).unwrap().is_empty());
+        let invalid = VectorRecord::new("a".into(), vec![f32::NAN, 0.0, 0.0, 0.0], "a".into());
+        assert!(matches!(buffer.insert(invalid), Err(Error::InvalidVector(_))));
+        let too_short = VectorRecord::new("a".into(), vec![1.0, 0.0], "a".into());
+        assert!(matches!(buffer.insert(too_short), Err(Error::InvalidConfig(_))));
+        assert_eq!(buffer.dim(), None);
+
+        let buffer = ShardedCircularBuffer::new(2, 4).unwrap();
+        buffer.insert(VectorRecord::new("a".into(), vec![1.0, 0.0, 0.0], "a".into())).unwrap();
+        assert_eq!(buffer.dim(), Some(3));
+
+        let wide = VectorRecord::new("b".into(), vec![1.0, 0.0, 0.0, 0.0], "b".into());
+        assert!(matches!(buffer.insert(wide), Err(Error::DimensionMismatch { expected: 3, actual: 4 })));
+        let narrow = VectorRecord::new("a".into(), vec![1.0, 0.0], "a".into());
+        assert!(matches!(buffer.upsert(narrow), Err(Error::DimensionMismatch { expected: 3, actual: 2 })));
+        assert!(matches!(buffer.search(&[1.0, 0.0], 5), Err(Error::DimensionMismatch { expected: 3, actual: 2 })));
+        assert_eq!(buffer.search(&[1.0, 0.0, 0.0], 5).unwrap().len(), 1);
+
+        // Declared up front.
+        let buffer = ShardedCircularBuffer::new(2, 4).unwrap().with_dim(2).unwrap();
+        assert_eq!(buffer.dim(), Some(2));
+        assert!(matches!(buffer.search(&[1.0, 0.0, 0.0], 5), Err(Error::DimensionMismatch { expected: 2, actual: 3 })));
+        let wide = VectorRecord::new("a".into(), vec![1.0, 0.0, 0.0], "a".into());
+        assert!(matches!(buffer.insert(wide), Err(Error::DimensionMismatch { expected: 2, actual: 3 })));
+        assert!(buffer.get("a").is_none());
+
+        assert!(matches!(ShardedCircularBuffer::new(2, 4).unwrap().with_dim(0), Err(Error::InvalidConfig(_))));
+        // Arenas that could never be allocated are rejected instead of panicking.
+        assert!(matches!(ShardedCircularBuffer::new(1, usize::MAX / 4), Err(Error::InvalidConfig(_))));
+        let oversized = ShardedCircularBuffer::new(1, 1024).unwrap().with_dim(usize::MAX / 1024);
+        assert!(matches!(oversized, Err(Error::InvalidConfig(_))));
+
+        // Settings that shape stored vectors are locked once a vector is stored.
+        let stored = || {
+            let buffer = ShardedCircularBuffer::new(2, 4).unwrap();
+            buffer.insert(VectorRecord::new("a".into(), vec![1.0, 0.0, 0.0], "a".into())).unwrap();
+            buffer
+        };
+        assert!(matches!(stored().with_dim(4), Err(Error::InvalidConfig(_))));
+        assert!(matches!(stored().with_truncate_dim(2), Err(Error::InvalidConfig(_))));
+        assert!(matches!(stored().with_metric(Metric::Dot), Err(Error::InvalidConfig(_))));
+        assert!(matches!(stored().with_storage(VectorStorage::Int8 { rerank: None }), Err(Error::InvalidConfig(_))));
+    }
+}
@@ -0,0 +1,42 @@
+use thiserror::Error;
+
+// Some variants only exist with the `embedder` feature. Non-exhaustive, so a match
+// written without the feature still compiles when another crate enables it.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("failed to load tokenizer: {0}")]
+    TokenizerLoad(String),
+
+    #[cfg(feature = "embedder")]
+    #[error("failed to load ONNX model: {0}")]
+    ModelLoad(#[source] ort::Error),
+
+    #[error("tokenization failed: {0}")]
+    Tokenization(String),
+
+    #[cfg(feature = "embedder")]
+    #[error("ONNX inference failed: {0}")]
+    Inference(#[from] ort::Error),
+
+    #[error("model has no output named '{0}'")]
+    MissingOutput(String),
+
+    #[error("embedder failed: {0}")]
+    Embedder(String),
+
+    #[cfg(feature = "embedder")]
+    #[error("unexpected model output shape: {0}")]
+    Shape(#[from] ndarray::ShapeError),
+
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    #[error("invalid vector: {0}")]
+    InvalidVector(String),
+
+    #[error("dimension mismatch: expected {expected}, got {actual}")]
+    DimensionMismatch { expected: usize, actual: usize },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
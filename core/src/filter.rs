@@ -0,0 +1,131 @@
+use std::cmp::Ordering;
+use crate::models::{AttributeValue, Attributes};
+
+/// A boolean expression over a record's attributes, evaluated inside the
+/// per-shard scan so non-matching records are never scored.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Eq(String, AttributeValue),
+    Ne(String, AttributeValue),
+    Gt(String, AttributeValue),
+    Gte(String, AttributeValue),
+    Lt(String, AttributeValue),
+    Lte(String, AttributeValue),
+    In(String, Vec<AttributeValue>),
+    Exists(String),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub fn eq(key: impl Into<String>, value: impl Into<AttributeValue>) -> Self {
+        Filter::Eq(key.into(), value.into())
+    }
+
+    /// Inclusive range `min <= key <= max`.
+    pub fn range(
+        key: impl Into<String>,
+        min: impl Into<AttributeValue>,
+        max: impl Into<AttributeValue>,
+    ) -> Self {
+        let key = key.into();
+        Filter::And(vec![
+            Filter::Gte(key.clone(), min.into()),
+            Filter::Lte(key, max.into()),
+        ])
+    }
+
+    pub fn is_in(key: impl Into<String>, values: Vec<AttributeValue>) -> Self {
+        Filter::In(key.into(), values)
+    }
+
+    pub fn and(self, other: Filter) -> Self {
+        match self {
+            Filter::And(mut filters) => {
+                filters.push(other);
+                Filter::And(filters)
+            }
+            filter => Filter::And(vec![filter, other]),
+        }
+    }
+
+    pub fn or(self, other: Filter) -> Self {
+        match self {
+            Filter::Or(mut filters) => {
+                filters.push(other);
+                Filter::Or(filters)
+            }
+            filter => Filter::Or(vec![filter, other]),
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Self {
+        Filter::Not(Box::new(self))
+    }
+
+    /// A missing attribute never satisfies a comparison, so `Ne` on a missing key is `false`.
+    pub fn matches(&self, attributes: &Attributes) -> bool {
+        match self {
+            Filter::Eq(key, value) => compare(attributes, key, value) == Some(Ordering::Equal),
+            Filter::Ne(key, value) => matches!(
+                compare(attributes, key, value),
+                Some(Ordering::Less | Ordering::Greater)
+            ),
+            Filter::Gt(key, value) => compare(attributes, key, value) == Some(Ordering::Greater),
+            Filter::Gte(key, value) => matches!(
+                compare(attributes, key, value),
+                Some(Ordering::Greater | Ordering::Equal)
+            ),
+            Filter::Lt(key, value) => compare(attributes, key, value) == Some(Ordering::Less),
+            Filter::Lte(key, value) => matches!(
+                compare(attributes, key, value),
+                Some(Ordering::Less | Ordering::Equal)
+            ),
+            Filter::In(key, values) => values
+                .iter()
+                .any(|value| compare(attributes, key, value) == Some(Ordering::Equal)),
+            Filter::Exists(key) => attributes.contains_key(key),
+            Filter::And(filters) => filters.iter().all(|f| f.matches(attributes)),
+            Filter::Or(filters) => filters.iter().any(|f| f.matches(attributes)),
+            Filter::Not(filter) => !filter.matches(attributes),
+        }
+    }
+}
+
+fn compare(attributes: &Attributes, key: &str, value: &AttributeValue) -> Option<Ordering> {
+    attributes.get(key)?.compare(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attrs() -> Attributes {
+        let mut attributes = Attributes::new();
+        attributes.insert("host".into(), "db-1".into());
+        attributes.insert("severity".into(), AttributeValue::Int(3));
+        attributes.insert("latency_ms".into(), AttributeValue::Float(250.0));
+        attributes.insert("paged".into(), true.into());
+        attributes
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        let attributes = attrs();
+
+        assert!(Filter::eq("host", "db-1").matches(&attributes));
+        assert!(!Filter::eq("host", "db-2").matches(&attributes));
+        assert!(Filter::range("latency_ms", 200, 300).matches(&attributes));
+        assert!(!Filter::Gt("severity".into(), AttributeValue::Float(3.0)).matches(&attributes));
+        assert!(Filter::is_in("host", vec!["db-0".into(), "db-1".into()]).matches(&attributes));
+        assert!(Filter::eq("paged", true).and(Filter::eq("host", "db-1")).matches(&attributes));
+        assert!(Filter::eq("host", "db-2").or(Filter::eq("severity", 3)).matches(&attributes));
+        assert!(Filter::eq("host", "db-2").not().matches(&attributes));
+
+        // Missing keys and mismatched types never match.
+        assert!(!Filter::Ne("tenant".into(), "acme".into()).matches(&attributes));
+        assert!(!Filter::eq("host", 1).matches(&attributes));
+    }
+}
@@ -1,4 +1,13 @@
+pub mod error;
 pub mod models;
 pub mod engine;
 pub mod search;
-pub mod embedder;
\ No newline at end of file
+pub mod simd;
+pub mod filter;
+pub mod embedding;
+pub mod batching;
+pub mod cache;
+#[cfg(feature = "embedder")]
+pub mod embedder;
+
+pub use error::{Error, Result};
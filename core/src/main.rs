@@ -1,37 +1,84 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::path::Path;
 use std::io::{self, BufRead, Write};
 use std::fs::File;
+use std::sync::mpsc;
 use std::thread;
+use std::time::Duration;
 
-use imesde::models::VectorRecord;
-use imesde::engine::{ShardedCircularBuffer, DEFAULT_NUM_SHARDS, DEFAULT_SHARD_SIZE};
+use imesde::batching::{BatchConfig, BatchingEmbedder};
+use imesde::cache::{CachingEmbedder, EmbeddingCache, DEFAULT_CACHE_SIZE};
+use imesde::models::{now_timestamp, timestamp_from_secs_f64, Attributes};
+use imesde::engine::{SearchOptions, ShardedCircularBuffer, DEFAULT_NUM_SHARDS, DEFAULT_SHARD_SIZE};
 use imesde::embedder::TextEmbedder;
+use imesde::embedding::{document_records, Embedder, HashEmbedder};
+
+const HASH_EMBEDDER_DIM: usize = 384;
+// Lines read ahead of the embedder. Bounds memory when stdin is faster than inference.
+const INGEST_QUEUE_SIZE: usize = 4096;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // With --event-time, every stdin line starts with its Unix timestamp in seconds
+    // (e.g. `1718000000.123 GET /health 200`), which is used as the record's event time.
+    let event_time = std::env::args().skip(1).any(|arg| arg == "--event-time");
+    // With --hash-embedder, a deterministic hashing embedder replaces the ONNX model, so the
+    // pipeline can be tried without downloading one. Its results are lexical, not semantic.
+    let hash_embedder = std::env::args().skip(1).any(|arg| arg == "--hash-embedder");
+    // --cache-size <n> bounds the embedding cache for repeated lines; 0 disables it.
+    let cache_size = match flag_value("--cache-size") {
+        Some(value) => value.parse::<usize>()
+            .map_err(|_| format!("Invalid --cache-size '{}'. Use a number of entries, or 0 to disable.", value))?,
+        None => DEFAULT_CACHE_SIZE,
+    };
+    // --truncate-dim <n> keeps the first n dimensions of each embedding (Matryoshka models only).
+    let truncate_dim = match flag_value("--truncate-dim") {
+        Some(value) => Some(value.parse::<usize>()
+            .map_err(|_| format!("Invalid --truncate-dim '{}'. Use a number of dimensions.", value))?),
+        None => None,
+    };
+
     // 1. Core Initialization
-    let buffer = Arc::new(ShardedCircularBuffer::new(DEFAULT_NUM_SHARDS, DEFAULT_SHARD_SIZE));
+    let mut buffer = ShardedCircularBuffer::new(DEFAULT_NUM_SHARDS, DEFAULT_SHARD_SIZE)?;
+    if let Some(truncate_dim) = truncate_dim {
+        buffer = buffer.with_truncate_dim(truncate_dim)?;
+    }
+    let buffer = Arc::new(buffer);
     let log_count = Arc::new(AtomicUsize::new(0));
 
     // 2. AI Initialization
-    let model_path = "model/model.onnx";
-    let tokenizer_path = "model/tokenizer.json";
+    let embedder: Arc<dyn Embedder> = if hash_embedder {
+        Arc::new(HashEmbedder::new(HASH_EMBEDDER_DIM)?)
+    } else {
+        let model_path = "model/model.onnx";
+        let tokenizer_path = "model/tokenizer.json";
 
-    if !Path::new(model_path).exists() || !Path::new(tokenizer_path).exists() {
-        eprintln!("❌ Error: Model files not found. Place them in 'model/' directory.");
-        std::process::exit(1);
-    }
+        if !Path::new(model_path).exists() || !Path::new(tokenizer_path).exists() {
+            eprintln!("❌ Error: Model files not found. Place them in 'model/' directory, or run with --hash-embedder.");
+            std::process::exit(1);
+        }
 
-    let embedder = Arc::new(TextEmbedder::new(model_path, tokenizer_path));
-    println!("🚀 Imesde Engine & AI Ready (Dim: {}).", embedder.dim);
-    println!("📝 Commands: /search <query>, /status, /exit");
+        Arc::new(TextEmbedder::new(model_path, tokenizer_path)?)
+    };
+    // Concurrent misses are embedded together. The cache wraps the batching stage, so
+    // repeated lines return at once instead of waiting for a batch.
+    let batch_config = BatchConfig::default();
+    let embedder: Arc<dyn Embedder> = Arc::new(BatchingEmbedder::new(embedder, batch_config)?);
+    let cache = (cache_size > 0).then(|| Arc::new(EmbeddingCache::new(cache_size)));
+    let embedder: Arc<dyn Embedder> = match &cache {
+        Some(cache) => Arc::new(CachingEmbedder::new(embedder, Arc::clone(cache))),
+        None => embedder,
+    };
+    println!("🚀 Imesde Engine & AI Ready (Dim: {}).", embedder.dim());
+    println!("📝 Commands: /search [--last <90s|5m|1h|7d>] [--until <window>] [--min <score>] <query>, /status, /exit");
     println!("--------------------------------------------------");
 
-    // 3. Background Ingestion Thread
-    let buffer_ingest = Arc::clone(&buffer);
-    let embedder_ingest = Arc::clone(&embedder);
-    let count_ingest = Arc::clone(&log_count);
+    // 3. Background Ingestion Threads
+    // The reader hands lines to a pool of ingest workers, one per batch slot, so lines
+    // arriving together are embedded in one batch. Records are stored as their
+    // embeddings complete, which may differ slightly from arrival order.
+    let (line_tx, line_rx) = mpsc::sync_channel(INGEST_QUEUE_SIZE);
+    let line_rx = Arc::new(Mutex::new(line_rx));
 
     thread::spawn(move || {
         let stdin = io::stdin();
@@ -41,23 +88,45 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         while let Ok(n) = reader.read_line(&mut line) {
             if n == 0 { break; } // EOF
             
-            let text = line.trim();
-            if !text.is_empty() {
-                // Generate embedding and insert
-                let vector = embedder_ingest.embed(text);
-                let current_id = count_ingest.fetch_add(1, Ordering::SeqCst);
-                
-                let record = VectorRecord::new(
-                    format!("log_{}", current_id),
-                    vector,
-                    text.to_string(),
-                );
-                buffer_ingest.insert(record);
+            let (timestamp, text) = if event_time {
+                split_event_time(line.trim())
+            } else {
+                (None, line.trim())
+            };
+            if !text.is_empty() && line_tx.send((text.to_string(), timestamp)).is_err() {
+                break;
             }
             line.clear();
         }
     });
 
+    for _ in 0..batch_config.max_batch_size {
+        let line_rx = Arc::clone(&line_rx);
+        let embedder = Arc::clone(&embedder);
+        let buffer_ingest = Arc::clone(&buffer);
+        let count_ingest = Arc::clone(&log_count);
+
+        thread::spawn(move || loop {
+            let next = line_rx.lock().expect("ingest queue poisoned").recv();
+            let Ok((text, timestamp)) = next else { break };
+            let chunks = match embedder.embed_documents(vec![text]) {
+                Ok(mut documents) => documents.pop().unwrap_or_default(),
+                Err(e) => {
+                    eprintln!("⚠️ Skipping line: {}", e);
+                    continue;
+                }
+            };
+            let current_id = count_ingest.fetch_add(1, Ordering::SeqCst);
+
+            let id = format!("log_{}", current_id);
+            for record in document_records(id, chunks, timestamp, Attributes::new()) {
+                if let Err(e) = buffer_ingest.insert(record) {
+                    eprintln!("⚠️ Skipping line: {}", e);
+                }
+            }
+        });
+    }
+
     // 4. Interactive UI Loop (Using /dev/tty to keep stdin free for pipes)
     let mut tty_reader = io::BufReader::new(File::open("/dev/tty")?);
     let mut input = String::new();
@@ -72,12 +141,33 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let cmd = input.trim();
         if cmd.is_empty() { continue; }
 
-        if cmd.starts_with("/search ") {
-            let query = &cmd[8..];
-            println!("🔍 Searching for: '{}'...", query);
-            
-            let query_vec = embedder.embed(query);
-            let results = buffer.search(&query_vec, 5);
+        if let Some(args) = cmd.strip_prefix("/search ") {
+            let args = match parse_search_args(args) {
+                Ok(parsed) => parsed,
+                Err(msg) => {
+                    println!("❓ {}", msg);
+                    continue;
+                }
+            };
+
+            let since = args.window.map(ago);
+            let until = args.until.map(ago);
+            println!("🔍 Searching for: '{}'...", args.query);
+
+            let results = match embedder.embed_query(args.query).and_then(|query_vec| match args.min_score {
+                // With a threshold, return every match instead of a fixed top-k.
+                Some(min_score) => {
+                    let options = SearchOptions::default().with_time_range(since, until).with_min_score(min_score);
+                    buffer.search_with(&query_vec, usize::MAX, &options)
+                }
+                None => buffer.search_time_range(&query_vec, 5, since, until),
+            }) {
+                Ok(results) => results,
+                Err(e) => {
+                    println!("❌ Search failed: {}", e);
+                    continue;
+                }
+            };
 
             if results.is_empty() {
                 println!("   No records found yet.");
@@ -89,13 +179,105 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         } else if cmd == "/status" {
             let total = log_count.load(Ordering::SeqCst);
             println!("📊 Status: {} logs ingested in circular buffer.", total);
+            if let Some(cache) = &cache {
+                let stats = cache.stats();
+                println!("   Embedding cache: {} hits, {} misses, {}/{} entries.",
+                    stats.hits, stats.misses, stats.len, stats.capacity);
+            }
         } else if cmd == "/exit" {
             println!("👋 Goodbye!");
             break;
         } else {
-            println!("❓ Unknown command. Use /search <query>, /status or /exit");
+            println!("❓ Unknown command. Use /search [--last <window>] [--until <window>] [--min <score>] <query>, /status or /exit");
         }
     }
 
     Ok(())
 }
+
+// Returns the argument following `flag`, e.g. `--cache-size 5000`.
+fn flag_value(flag: &str) -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    args.find(|arg| arg == flag)?;
+    args.next()
+}
+
+// Splits a leading Unix timestamp off a log line. Lines without one keep their
+// full text and fall back to the ingest time.
+fn split_event_time(line: &str) -> (Option<u64>, &str) {
+    if let Some((head, rest)) = line.split_once(char::is_whitespace)
+        && let Some(ts) = head.parse::<f64>().ok().and_then(timestamp_from_secs_f64)
+    {
+        return (Some(ts), rest.trim_start());
+    }
+    (None, line)
+}
+
+struct SearchArgs<'a> {
+    window: Option<Duration>,
+    // Records newer than this are skipped, so `--last 1h --until 10m` searches [1h ago, 10m ago].
+    until: Option<Duration>,
+    min_score: Option<f32>,
+    query: &'a str,
+}
+
+// Parses `[--last <window>] [--until <window>] [--min <score>] <query>`; flags may appear
+// in any order.
+fn parse_search_args(args: &str) -> Result<SearchArgs<'_>, String> {
+    let mut parsed = SearchArgs { window: None, until: None, min_score: None, query: args.trim() };
+    while let Some(rest) = parsed.query.strip_prefix("--") {
+        let (flag, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        let rest = rest.trim_start();
+        let (value, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        match flag {
+            "last" => {
+                let window = parse_duration(value)
+                    .ok_or_else(|| format!("Invalid window '{}'. Use e.g. 90s, 5m, 1h or 7d.", value))?;
+                parsed.window = Some(window);
+            }
+            "until" => {
+                let until = parse_duration(value)
+                    .ok_or_else(|| format!("Invalid window '{}'. Use e.g. --until 10m for records older than 10 minutes.", value))?;
+                parsed.until = Some(until);
+            }
+            "min" => {
+                let min_score = value.parse::<f32>()
+                    .map_err(|_| format!("Invalid score '{}'. Use e.g. --min 0.6", value))?;
+                parsed.min_score = Some(min_score);
+            }
+            _ => return Err(format!("Unknown flag '--{}'.", flag)),
+        }
+        parsed.query = rest.trim();
+    }
+    if parsed.query.is_empty() {
+        return Err("Missing query. Use /search [--last <window>] [--until <window>] [--min <score>] <query>".to_string());
+    }
+    if let (Some(window), Some(until)) = (parsed.window, parsed.until)
+        && until >= window
+    {
+        return Err("--until must be shorter than --last, e.g. --last 1h --until 10m".to_string());
+    }
+    Ok(parsed)
+}
+
+// The record timestamp `window` before now.
+fn ago(window: Duration) -> u64 {
+    now_timestamp().saturating_sub(u64::try_from(window.as_nanos()).unwrap_or(u64::MAX))
+}
+
+// Parses durations like `90s`, `5m`, `1h`, `7d` or a bare number of seconds.
+// Values too large to represent are rejected rather than wrapped.
+fn parse_duration(s: &str) -> Option<Duration> {
+    let (value, multiplier) = if let Some(v) = s.strip_suffix('s') {
+        (v, 1)
+    } else if let Some(v) = s.strip_suffix('m') {
+        (v, 60)
+    } else if let Some(v) = s.strip_suffix('h') {
+        (v, 3600)
+    } else if let Some(v) = s.strip_suffix('d') {
+        (v, 86_400)
+    } else {
+        (s, 1)
+    };
+    value.parse::<u64>().ok()?.checked_mul(multiplier).map(Duration::from_secs)
+}
@@ -1,25 +1,157 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// A typed attribute value attached to a record (e.g. source, host, severity, tenant).
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Timestamp(SystemTime),
+}
+
+impl AttributeValue {
+    /// Orders two values of compatible types. Ints and floats compare numerically;
+    /// any other cross-type comparison is `None`.
+    pub fn compare(&self, other: &AttributeValue) -> Option<Ordering> {
+        use AttributeValue::*;
+        match (self, other) {
+            (Str(a), Str(b)) => Some(a.cmp(b)),
+            (Int(a), Int(b)) => Some(a.cmp(b)),
+            (Float(a), Float(b)) => a.partial_cmp(b),
+            (Int(a), Float(b)) => (*a as f64).partial_cmp(b),
+            (Float(a), Int(b)) => a.partial_cmp(&(*b as f64)),
+            (Bool(a), Bool(b)) => Some(a.cmp(b)),
+            (Timestamp(a), Timestamp(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        AttributeValue::Str(value.to_string())
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> Self {
+        AttributeValue::Str(value)
+    }
+}
+
+impl From<i64> for AttributeValue {
+    fn from(value: i64) -> Self {
+        AttributeValue::Int(value)
+    }
+}
+
+impl From<i32> for AttributeValue {
+    fn from(value: i32) -> Self {
+        AttributeValue::Int(value as i64)
+    }
+}
+
+impl From<f64> for AttributeValue {
+    fn from(value: f64) -> Self {
+        AttributeValue::Float(value)
+    }
+}
+
+impl From<bool> for AttributeValue {
+    fn from(value: bool) -> Self {
+        AttributeValue::Bool(value)
+    }
+}
+
+impl From<SystemTime> for AttributeValue {
+    fn from(value: SystemTime) -> Self {
+        AttributeValue::Timestamp(value)
+    }
+}
+
+pub type Attributes = BTreeMap<String, AttributeValue>;
+
+pub const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Converts a point in time to the unit used by record timestamps (nanoseconds since the Unix epoch).
+/// Times before the epoch clamp to 0, and times past the year 2554 to `u64::MAX`.
+pub fn to_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| u64::try_from(since_epoch.as_nanos()).unwrap_or(u64::MAX))
+}
+
+/// Converts Unix seconds (e.g. Python's `time.time()`) to a record timestamp.
+pub fn timestamp_from_secs_f64(secs: f64) -> Option<u64> {
+    (secs.is_finite() && secs >= 0.0).then_some((secs * NANOS_PER_SEC as f64) as u64)
+}
+
+pub fn now_timestamp() -> u64 {
+    to_timestamp(SystemTime::now())
+}
+
+/// Attribute linking a chunk record to the id of the document it was split from.
+pub const PARENT_ID_ATTRIBUTE: &str = "parent_id";
+/// Attribute holding a chunk's position within its parent document.
+pub const CHUNK_INDEX_ATTRIBUTE: &str = "chunk_index";
+
+/// Id of chunk `index` of the document `parent_id`.
+pub fn chunk_id(parent_id: &str, index: usize) -> String {
+    format!("{}#{}", parent_id, index)
+}
+
 #[derive(Debug, Clone)]
 pub struct VectorRecord {
     pub id: String,
+    /// Moved into the shard's vector arena on insert. Records returned by `get`, and by a
+    /// search with `SearchOptions::with_vectors`, carry a copy of the stored vector:
+    /// truncated and normalized as the buffer stores it, and dequantized under int8
+    /// storage without re-ranking. Other search hits carry an empty vector.
     pub vector: Vec<f32>,
+    /// Event time in nanoseconds since the Unix epoch. Defaults to the time the record was created.
     pub timestamp: u64,
+    /// When the record entered the engine, in nanoseconds since the Unix epoch. Set by
+    /// the buffer on insert.
+    pub ingested_at: u64,
     pub metadata: String,
+    pub attributes: Attributes,
 }
 
 impl VectorRecord {
     pub fn new(id: String, vector: Vec<f32>, metadata: String) -> Self {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_secs();
-
+        let now = now_timestamp();
         Self {
             id,
             vector,
-            timestamp,
+            timestamp: now,
+            ingested_at: now,
             metadata,
+            attributes: Attributes::new(),
         }
     }
+
+    /// Creates a record for an event that happened at `event_time` (nanoseconds since the
+    /// Unix epoch), e.g. when replaying logs. `ingested_at` is still the current time.
+    pub fn new_with_event_time(id: String, vector: Vec<f32>, metadata: String, event_time: u64) -> Self {
+        let mut record = Self::new(id, vector, metadata);
+        record.timestamp = event_time;
+        record
+    }
+
+    pub fn with_attributes(mut self, attributes: Attributes) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
+    /// Turns the record into chunk `index` of the document `parent_id`. The id becomes
+    /// [`chunk_id`] and the parent id and index are added as attributes, so all chunks
+    /// of a document can be found with a filter.
+    pub fn into_chunk_of(mut self, parent_id: &str, index: usize) -> Self {
+        self.id = chunk_id(parent_id, index);
+        self.attributes.insert(PARENT_ID_ATTRIBUTE.to_string(), parent_id.into());
+        self.attributes.insert(CHUNK_INDEX_ATTRIBUTE.to_string(), (index as i64).into());
+        self
+    }
 }
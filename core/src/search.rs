@@ -1,27 +1,226 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+use crate::simd;
+
+/// Dot product using the fastest SIMD kernel the CPU supports. Returns 0 when the
+/// lengths differ.
 pub fn dot_product(v1: &[f32], v2: &[f32]) -> f32 {
     let len = v1.len();
     if len != v2.len() || len == 0 {
         return 0.0;
     }
-
-    // Manual unrolling/hinting often helps the compiler verify purely safe access
-    // isn't bound-checked inside the hot loop.
-    let mut sum = 0.0;
-    for i in 0..len {
-        // SAFETY: We checked lengths are equal.
-        // Using get_unchecked would be unsafe but faster. 
-        // For now, simple indexing allows auto-vectorization if compiled with -C target-cpu=native
-        sum += v1[i] * v2[i];
-    }
-    sum
+    (simd::kernels().dot_f32)(v1, v2)
 }
 
-// Since our vectors are already normalized in the embedder, 
-// cosine similarity is just the dot product.
+/// Cosine similarity of unit vectors, which is just their dot product. Buffers using
+/// [`Metric::Cosine`] normalize vectors and queries, so inputs of any norm rank correctly.
 pub fn cosine_similarity(v1: &[f32], v2: &[f32]) -> f32 {
     dot_product(v1, v2)
 }
 
+/// Squared Euclidean distance. Returns infinity when the lengths differ.
+pub fn squared_l2_distance(v1: &[f32], v2: &[f32]) -> f32 {
+    if v1.len() != v2.len() {
+        return f32::INFINITY;
+    }
+    (simd::kernels().squared_l2)(v1, v2)
+}
+
+/// Sum of absolute differences. Returns infinity when the lengths differ.
+pub fn manhattan_distance(v1: &[f32], v2: &[f32]) -> f32 {
+    if v1.len() != v2.len() {
+        return f32::INFINITY;
+    }
+    (simd::kernels().manhattan)(v1, v2)
+}
+
+/// Scales `vector` to unit length. Zero vectors are left unchanged.
+pub fn normalize(vector: &mut [f32]) {
+    let norm = dot_product(vector, vector).sqrt();
+    if norm > f32::EPSILON {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// How a buffer compares vectors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Metric {
+    /// Cosine similarity. Vectors and queries are normalized, so any input is ranked correctly.
+    #[default]
+    Cosine,
+    /// Inner product of the vectors as given; the norm counts.
+    Dot,
+    /// Squared Euclidean distance; smaller is better.
+    SquaredL2,
+    /// Sum of absolute differences; smaller is better.
+    Manhattan,
+}
+
+impl Metric {
+    pub fn score(&self, v1: &[f32], v2: &[f32]) -> f32 {
+        match self {
+            Metric::Cosine | Metric::Dot => dot_product(v1, v2),
+            Metric::SquaredL2 => squared_l2_distance(v1, v2),
+            Metric::Manhattan => manhattan_distance(v1, v2),
+        }
+    }
+
+    /// `false` for distances, where the best match has the smallest score.
+    pub fn higher_is_better(&self) -> bool {
+        matches!(self, Metric::Cosine | Metric::Dot)
+    }
+
+    /// Whether `score` is at least as good as `threshold`.
+    pub fn passes(&self, score: f32, threshold: f32) -> bool {
+        if self.higher_is_better() { score >= threshold } else { score <= threshold }
+    }
+
+    /// Maps a score to a key where larger is always better.
+    pub fn rank_key(&self, score: f32) -> f32 {
+        if self.higher_is_better() { score } else { -score }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "cosine" => Ok(Metric::Cosine),
+            "dot" | "inner_product" => Ok(Metric::Dot),
+            "l2" | "squared_l2" | "euclidean" => Ok(Metric::SquaredL2),
+            "l1" | "manhattan" => Ok(Metric::Manhattan),
+            _ => Err(Error::InvalidConfig(format!(
+                "unknown metric '{}' (expected cosine, dot, l2 or manhattan)", s
+            ))),
+        }
+    }
+}
+
+/// A vector scalar-quantized to int8 with one scale per vector: `x ≈ value as f32 * scale`.
+/// Takes a quarter of the memory of the float vector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizedVector {
+    pub values: Vec<i8>,
+    pub scale: f32,
+}
+
+impl QuantizedVector {
+    /// Maps the largest magnitude component to ±127.
+    pub fn quantize(vector: &[f32]) -> Self {
+        let max = vector.iter().fold(0.0f32, |max, x| max.max(x.abs()));
+        let scale = if max > 0.0 { max / 127.0 } else { 1.0 };
+        let values = vector.iter()
+            .map(|x| (x / scale).round().clamp(-127.0, 127.0) as i8)
+            .collect();
+        Self { values, scale }
+    }
+
+    /// Approximate dot product with the quantized vector `(values, scale)`.
+    pub fn dot(&self, values: &[i8], scale: f32) -> f32 {
+        dot_product_i8(&self.values, values) as f32 * self.scale * scale
+    }
+}
+
+/// Exact integer dot product of two int8 vectors. Returns 0 when the lengths differ.
+pub fn dot_product_i8(v1: &[i8], v2: &[i8]) -> i32 {
+    if v1.len() != v2.len() {
+        return 0;
+    }
+    (simd::kernels().dot_i8)(v1, v2)
+}
+
+/// The sign bits of a vector, 64 dimensions per word. The Hamming distance between two
+/// codes estimates the angle between the vectors at 1/32 of the memory of `f32`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinaryCode {
+    pub bits: Vec<u64>,
+    pub len: usize,
+}
+
+impl BinaryCode {
+    /// Sets bit `i` when component `i` is positive.
+    pub fn from_vector(vector: &[f32]) -> Self {
+        let mut bits = vec![0u64; vector.len().div_ceil(64)];
+        for (i, x) in vector.iter().enumerate() {
+            if *x > 0.0 {
+                bits[i / 64] |= 1 << (i % 64);
+            }
+        }
+        Self { bits, len: vector.len() }
+    }
+
+    /// Number of bits differing from the code words `bits`. A code of another length
+    /// is maximally distant.
+    pub fn hamming(&self, bits: &[u64]) -> u32 {
+        if bits.len() != self.bits.len() {
+            return self.len as u32;
+        }
+        hamming_distance(&self.bits, bits)
+    }
+
+    /// Maps the Hamming distance to `[-1, 1]`, so it can be ranked like a similarity.
+    pub fn similarity(&self, bits: &[u64]) -> f32 {
+        if self.len == 0 {
+            return 0.0;
+        }
+        1.0 - 2.0 * self.hamming(bits) as f32 / self.len as f32
+    }
+}
+
+/// Number of differing bits between two bit strings. Returns `u32::MAX` when the lengths
+/// differ, so a malformed code ranks last rather than first.
+pub fn hamming_distance(v1: &[u64], v2: &[u64]) -> u32 {
+    if v1.len() != v2.len() {
+        return u32::MAX;
+    }
+    (simd::kernels().hamming)(v1, v2)
+}
+
+/// How a candidate's final score is derived from its similarity to the query.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ScoringMode {
+    /// Rank by the buffer's metric score only.
+    #[default]
+    Similarity,
+    /// Blend similarity with an exponential decay on the record's age:
+    /// `score = (1 - weight) * similarity + weight * 0.5^(age / half_life)`.
+    RecencyWeighted { half_life: Duration, weight: f32 },
+}
+
+/// The components that make up a search hit's final score.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreBreakdown {
+    pub score: f32,
+    pub similarity: f32,
+    /// Time-decay factor in `[0, 1]`; `None` when the scoring mode ignores time.
+    pub recency: Option<f32>,
+}
+
+impl ScoringMode {
+    pub fn recency_weighted(half_life: Duration, weight: f32) -> Self {
+        ScoringMode::RecencyWeighted { half_life, weight: weight.clamp(0.0, 1.0) }
+    }
+
+    /// Scores a candidate whose timestamp is `age_nanos` in the past.
+    pub fn score(&self, similarity: f32, age_nanos: u64) -> ScoreBreakdown {
+        match *self {
+            ScoringMode::Similarity => ScoreBreakdown { score: similarity, similarity, recency: None },
+            ScoringMode::RecencyWeighted { half_life, weight } => {
+                let half_lives = age_nanos as f64 / half_life.as_nanos().max(1) as f64;
+                let recency = (-half_lives).exp2() as f32;
+                ScoreBreakdown {
+                    score: (1.0 - weight) * similarity + weight * recency,
+                    similarity,
+                    recency: Some(recency),
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,4 +240,75 @@ mod tests {
         let sim_opp = cosine_similarity(&v1, &v4);
         assert!((sim_opp + 1.0).abs() < f32::EPSILON);
     }
+
+    #[test]
+    fn test_metrics() {
+        let v1 = [3.0, 4.0];
+        let v2 = [0.0, 1.0];
+        assert_eq!(Metric::Dot.score(&v1, &v2), 4.0);
+        assert_eq!(Metric::SquaredL2.score(&v1, &v2), 18.0);
+        assert_eq!(Metric::Manhattan.score(&v1, &v2), 6.0);
+        assert_eq!(Metric::SquaredL2.score(&v1, &[1.0]), f32::INFINITY);
+
+        assert!(Metric::Cosine.passes(0.8, 0.5) && !Metric::Cosine.passes(0.4, 0.5));
+        assert!(Metric::SquaredL2.passes(0.4, 0.5) && !Metric::SquaredL2.passes(0.8, 0.5));
+        assert!(Metric::Manhattan.rank_key(1.0) > Metric::Manhattan.rank_key(2.0));
+        assert_eq!("L2".parse::<Metric>().unwrap(), Metric::SquaredL2);
+        assert!("hamming".parse::<Metric>().is_err());
+
+        let mut v = v1;
+        normalize(&mut v);
+        assert_eq!(v, [0.6, 0.8]);
+        let mut zero = [0.0, 0.0];
+        normalize(&mut zero);
+        assert_eq!(zero, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_int8_dot_product() {
+        assert_eq!(dot_product_i8(&[1, -2, 127], &[3, 4, -127]), 3 - 8 - 16129);
+        assert_eq!(dot_product_i8(&[1, 2], &[1]), 0);
+
+        let v1 = [0.6, -0.8, 0.0];
+        let v2 = [0.8, 0.6, 0.0];
+        let q1 = QuantizedVector::quantize(&v1);
+        assert_eq!(q1.values, vec![95, -127, 0]);
+        let q2 = QuantizedVector::quantize(&v2);
+        assert!((q1.dot(&q1.values, q1.scale) - 1.0).abs() < 0.01);
+        assert!(q1.dot(&q2.values, q2.scale).abs() < 0.01);
+        assert_eq!(QuantizedVector::quantize(&[0.0, 0.0]).dot(&q1.values, q1.scale), 0.0);
+    }
+
+    #[test]
+    fn test_binary_code() {
+        let mut vector = vec![-1.0; 70];
+        vector[0] = 0.5;
+        vector[65] = 2.0;
+        let code = BinaryCode::from_vector(&vector);
+        assert_eq!(code.bits, vec![1, 2]);
+        assert_eq!(code.hamming(&code.bits), 0);
+        assert_eq!(code.similarity(&code.bits), 1.0);
+
+        let opposite = BinaryCode::from_vector(&vector.iter().map(|x| -x).collect::<Vec<_>>());
+        assert_eq!(code.hamming(&opposite.bits), 70);
+        assert_eq!(code.similarity(&opposite.bits), -1.0);
+        assert_eq!(code.hamming(&[1]), 70);
+        assert_eq!(hamming_distance(&code.bits, &[1]), u32::MAX);
+    }
+
+    #[test]
+    fn test_recency_weighted_score() {
+        let mode = ScoringMode::recency_weighted(Duration::from_secs(60), 0.5);
+
+        let fresh = mode.score(0.8, 0);
+        assert_eq!(fresh.recency, Some(1.0));
+        assert!((fresh.score - 0.9).abs() < 1e-6);
+
+        let one_half_life = mode.score(0.8, 60_000_000_000);
+        assert!((one_half_life.recency.unwrap() - 0.5).abs() < 1e-6);
+        assert!((one_half_life.score - 0.65).abs() < 1e-6);
+
+        let plain = ScoringMode::Similarity.score(0.8, 60_000_000_000);
+        assert_eq!(plain, ScoreBreakdown { score: 0.8, similarity: 0.8, recency: None });
+    }
 }
@@ -0,0 +1,484 @@
+use std::sync::OnceLock;
+
+/// A set of search kernels for one instruction set. Every kernel expects slices of equal
+/// length; callers check lengths first.
+pub struct Kernels {
+    pub name: &'static str,
+    pub dot_f32: fn(&[f32], &[f32]) -> f32,
+    pub squared_l2: fn(&[f32], &[f32]) -> f32,
+    pub manhattan: fn(&[f32], &[f32]) -> f32,
+    pub dot_i8: fn(&[i8], &[i8]) -> i32,
+    pub hamming: fn(&[u64], &[u64]) -> u32,
+}
+
+/// Plain loops, used when the CPU has none of the instruction sets below.
+pub static SCALAR: Kernels = Kernels {
+    name: "scalar",
+    dot_f32: scalar::dot_f32,
+    squared_l2: scalar::squared_l2,
+    manhattan: scalar::manhattan,
+    dot_i8: scalar::dot_i8,
+    hamming: scalar::hamming,
+};
+
+/// The fastest kernels this CPU supports, detected on first use.
+pub fn kernels() -> &'static Kernels {
+    static SELECTED: OnceLock<&'static Kernels> = OnceLock::new();
+    SELECTED.get_or_init(|| available().pop().unwrap_or(&SCALAR))
+}
+
+/// Every kernel set this CPU supports, slowest first. The scalar set is always included.
+pub fn available() -> Vec<&'static Kernels> {
+    let mut kernels = vec![&SCALAR];
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") && is_x86_feature_detected!("popcnt") {
+            kernels.push(&x86::AVX2);
+        }
+        if is_x86_feature_detected!("avx512f")
+            && is_x86_feature_detected!("avx512bw")
+            && is_x86_feature_detected!("avx512vpopcntdq")
+            && is_x86_feature_detected!("popcnt")
+        {
+            kernels.push(&x86::AVX512);
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            kernels.push(&neon::NEON);
+        }
+    }
+    kernels
+}
+
+mod scalar {
+    pub fn dot_f32(v1: &[f32], v2: &[f32]) -> f32 {
+        v1.iter().zip(v2).map(|(a, b)| a * b).sum()
+    }
+
+    pub fn squared_l2(v1: &[f32], v2: &[f32]) -> f32 {
+        v1.iter().zip(v2).map(|(a, b)| (a - b) * (a - b)).sum()
+    }
+
+    pub fn manhattan(v1: &[f32], v2: &[f32]) -> f32 {
+        v1.iter().zip(v2).map(|(a, b)| (a - b).abs()).sum()
+    }
+
+    // Widening to i32 per product cannot overflow: |sum| <= 128 * 128 * len.
+    pub fn dot_i8(v1: &[i8], v2: &[i8]) -> i32 {
+        v1.iter().zip(v2).map(|(&a, &b)| a as i32 * b as i32).sum()
+    }
+
+    pub fn hamming(v1: &[u64], v2: &[u64]) -> u32 {
+        v1.iter().zip(v2).map(|(a, b)| (a ^ b).count_ones()).sum()
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::{scalar, Kernels};
+
+    // The safe wrappers below are only reachable through these sets, which `available`
+    // hands out after detecting the required CPU features.
+    pub static AVX2: Kernels = Kernels {
+        name: "avx2",
+        dot_f32: |v1, v2| unsafe { dot_f32_avx2(v1, v2) },
+        squared_l2: |v1, v2| unsafe { squared_l2_avx2(v1, v2) },
+        manhattan: |v1, v2| unsafe { manhattan_avx2(v1, v2) },
+        dot_i8: |v1, v2| unsafe { dot_i8_avx2(v1, v2) },
+        hamming: |v1, v2| unsafe { hamming_popcnt(v1, v2) },
+    };
+
+    pub static AVX512: Kernels = Kernels {
+        name: "avx512",
+        dot_f32: |v1, v2| unsafe { dot_f32_avx512(v1, v2) },
+        squared_l2: |v1, v2| unsafe { squared_l2_avx512(v1, v2) },
+        manhattan: |v1, v2| unsafe { manhattan_avx512(v1, v2) },
+        dot_i8: |v1, v2| unsafe { dot_i8_avx512(v1, v2) },
+        hamming: |v1, v2| unsafe { hamming_avx512(v1, v2) },
+    };
+
+    #[target_feature(enable = "avx2,fma")]
+    fn dot_f32_avx2(v1: &[f32], v2: &[f32]) -> f32 {
+        let len = v1.len().min(v2.len());
+        let (p1, p2) = (v1.as_ptr(), v2.as_ptr());
+        // Four accumulators hide the latency of the dependent FMAs.
+        let mut acc = [_mm256_setzero_ps(); 4];
+        let mut i = 0;
+        // SAFETY: every load reads 8 floats starting at `i`, with `i + 8 <= len`.
+        unsafe {
+            while i + 32 <= len {
+                for (j, acc) in acc.iter_mut().enumerate() {
+                    let offset = i + j * 8;
+                    *acc = _mm256_fmadd_ps(_mm256_loadu_ps(p1.add(offset)), _mm256_loadu_ps(p2.add(offset)), *acc);
+                }
+                i += 32;
+            }
+            while i + 8 <= len {
+                acc[0] = _mm256_fmadd_ps(_mm256_loadu_ps(p1.add(i)), _mm256_loadu_ps(p2.add(i)), acc[0]);
+                i += 8;
+            }
+        }
+        let sum = _mm256_add_ps(_mm256_add_ps(acc[0], acc[1]), _mm256_add_ps(acc[2], acc[3]));
+        hsum_avx(sum) + scalar::dot_f32(&v1[i..len], &v2[i..len])
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    fn squared_l2_avx2(v1: &[f32], v2: &[f32]) -> f32 {
+        let len = v1.len().min(v2.len());
+        let (p1, p2) = (v1.as_ptr(), v2.as_ptr());
+        let mut acc = [_mm256_setzero_ps(); 2];
+        let mut i = 0;
+        // SAFETY: every load reads 8 floats starting at `i`, with `i + 8 <= len`.
+        unsafe {
+            while i + 8 <= len {
+                let diff = _mm256_sub_ps(_mm256_loadu_ps(p1.add(i)), _mm256_loadu_ps(p2.add(i)));
+                let lane = (i / 8) % 2;
+                acc[lane] = _mm256_fmadd_ps(diff, diff, acc[lane]);
+                i += 8;
+            }
+        }
+        hsum_avx(_mm256_add_ps(acc[0], acc[1])) + scalar::squared_l2(&v1[i..len], &v2[i..len])
+    }
+
+    #[target_feature(enable = "avx2")]
+    fn manhattan_avx2(v1: &[f32], v2: &[f32]) -> f32 {
+        let len = v1.len().min(v2.len());
+        let (p1, p2) = (v1.as_ptr(), v2.as_ptr());
+        // Clearing the sign bit is the absolute value.
+        let sign = _mm256_set1_ps(-0.0);
+        let mut acc = _mm256_setzero_ps();
+        let mut i = 0;
+        // SAFETY: every load reads 8 floats starting at `i`, with `i + 8 <= len`.
+        unsafe {
+            while i + 8 <= len {
+                let diff = _mm256_sub_ps(_mm256_loadu_ps(p1.add(i)), _mm256_loadu_ps(p2.add(i)));
+                acc = _mm256_add_ps(acc, _mm256_andnot_ps(sign, diff));
+                i += 8;
+            }
+        }
+        hsum_avx(acc) + scalar::manhattan(&v1[i..len], &v2[i..len])
+    }
+
+    #[target_feature(enable = "avx")]
+    fn hsum_avx(sum: __m256) -> f32 {
+        let sum = _mm_add_ps(_mm256_castps256_ps128(sum), _mm256_extractf128_ps(sum, 1));
+        let sum = _mm_add_ps(sum, _mm_movehl_ps(sum, sum));
+        let sum = _mm_add_ss(sum, _mm_shuffle_ps(sum, sum, 1));
+        _mm_cvtss_f32(sum)
+    }
+
+    #[target_feature(enable = "avx2")]
+    fn dot_i8_avx2(v1: &[i8], v2: &[i8]) -> i32 {
+        let len = v1.len().min(v2.len());
+        let (p1, p2) = (v1.as_ptr(), v2.as_ptr());
+        let mut acc = _mm256_setzero_si256();
+        let mut i = 0;
+        // SAFETY: every load reads 16 bytes starting at `i`, with `i + 16 <= len`.
+        unsafe {
+            while i + 16 <= len {
+                // Sign-extend to i16; `madd` multiplies and adds neighbouring pairs into i32.
+                let a = _mm256_cvtepi8_epi16(_mm_loadu_si128(p1.add(i) as *const __m128i));
+                let b = _mm256_cvtepi8_epi16(_mm_loadu_si128(p2.add(i) as *const __m128i));
+                acc = _mm256_add_epi32(acc, _mm256_madd_epi16(a, b));
+                i += 16;
+            }
+        }
+        let sum = _mm_add_epi32(_mm256_castsi256_si128(acc), _mm256_extracti128_si256(acc, 1));
+        let sum = _mm_add_epi32(sum, _mm_shuffle_epi32(sum, 0b01_00_11_10));
+        let sum = _mm_add_epi32(sum, _mm_shuffle_epi32(sum, 0b10_11_00_01));
+        _mm_cvtsi128_si32(sum) + scalar::dot_i8(&v1[i..len], &v2[i..len])
+    }
+
+    // With POPCNT enabled, `count_ones` compiles to one instruction per word.
+    #[target_feature(enable = "popcnt")]
+    fn hamming_popcnt(v1: &[u64], v2: &[u64]) -> u32 {
+        scalar::hamming(v1, v2)
+    }
+
+    #[target_feature(enable = "avx512f")]
+    fn dot_f32_avx512(v1: &[f32], v2: &[f32]) -> f32 {
+        let len = v1.len().min(v2.len());
+        let (p1, p2) = (v1.as_ptr(), v2.as_ptr());
+        let mut acc = [_mm512_setzero_ps(); 2];
+        let mut i = 0;
+        // SAFETY: full loads read 16 floats with `i + 16 <= len`; the masked tail load
+        // only touches the `len - i` remaining ones.
+        unsafe {
+            while i + 32 <= len {
+                acc[0] = _mm512_fmadd_ps(_mm512_loadu_ps(p1.add(i)), _mm512_loadu_ps(p2.add(i)), acc[0]);
+                acc[1] = _mm512_fmadd_ps(_mm512_loadu_ps(p1.add(i + 16)), _mm512_loadu_ps(p2.add(i + 16)), acc[1]);
+                i += 32;
+            }
+            while i + 16 <= len {
+                acc[0] = _mm512_fmadd_ps(_mm512_loadu_ps(p1.add(i)), _mm512_loadu_ps(p2.add(i)), acc[0]);
+                i += 16;
+            }
+            if i < len {
+                let mask: __mmask16 = (1 << (len - i)) - 1;
+                let a = _mm512_maskz_loadu_ps(mask, p1.add(i));
+                let b = _mm512_maskz_loadu_ps(mask, p2.add(i));
+                acc[1] = _mm512_fmadd_ps(a, b, acc[1]);
+            }
+        }
+        _mm512_reduce_add_ps(_mm512_add_ps(acc[0], acc[1]))
+    }
+
+    #[target_feature(enable = "avx512f")]
+    fn squared_l2_avx512(v1: &[f32], v2: &[f32]) -> f32 {
+        let len = v1.len().min(v2.len());
+        let (p1, p2) = (v1.as_ptr(), v2.as_ptr());
+        let mut acc = _mm512_setzero_ps();
+        let mut i = 0;
+        // SAFETY: as in `dot_f32_avx512`.
+        unsafe {
+            while i < len {
+                let mask: __mmask16 = if i + 16 <= len { !0 } else { (1 << (len - i)) - 1 };
+                let diff = _mm512_sub_ps(_mm512_maskz_loadu_ps(mask, p1.add(i)), _mm512_maskz_loadu_ps(mask, p2.add(i)));
+                acc = _mm512_fmadd_ps(diff, diff, acc);
+                i += 16;
+            }
+        }
+        _mm512_reduce_add_ps(acc)
+    }
+
+    #[target_feature(enable = "avx512f")]
+    fn manhattan_avx512(v1: &[f32], v2: &[f32]) -> f32 {
+        let len = v1.len().min(v2.len());
+        let (p1, p2) = (v1.as_ptr(), v2.as_ptr());
+        let mut acc = _mm512_setzero_ps();
+        let mut i = 0;
+        // SAFETY: as in `dot_f32_avx512`.
+        unsafe {
+            while i < len {
+                let mask: __mmask16 = if i + 16 <= len { !0 } else { (1 << (len - i)) - 1 };
+                let diff = _mm512_sub_ps(_mm512_maskz_loadu_ps(mask, p1.add(i)), _mm512_maskz_loadu_ps(mask, p2.add(i)));
+                acc = _mm512_add_ps(acc, _mm512_abs_ps(diff));
+                i += 16;
+            }
+        }
+        _mm512_reduce_add_ps(acc)
+    }
+
+    #[target_feature(enable = "avx512f,avx512bw")]
+    fn dot_i8_avx512(v1: &[i8], v2: &[i8]) -> i32 {
+        let len = v1.len().min(v2.len());
+        let (p1, p2) = (v1.as_ptr(), v2.as_ptr());
+        let mut acc = _mm512_setzero_si512();
+        let mut i = 0;
+        // SAFETY: every load reads 32 bytes starting at `i`, with `i + 32 <= len`.
+        unsafe {
+            while i + 32 <= len {
+                let a = _mm512_cvtepi8_epi16(_mm256_loadu_si256(p1.add(i) as *const __m256i));
+                let b = _mm512_cvtepi8_epi16(_mm256_loadu_si256(p2.add(i) as *const __m256i));
+                acc = _mm512_add_epi32(acc, _mm512_madd_epi16(a, b));
+                i += 32;
+            }
+        }
+        _mm512_reduce_add_epi32(acc) + scalar::dot_i8(&v1[i..len], &v2[i..len])
+    }
+
+    #[target_feature(enable = "avx512f,avx512vpopcntdq,popcnt")]
+    fn hamming_avx512(v1: &[u64], v2: &[u64]) -> u32 {
+        let len = v1.len().min(v2.len());
+        let (p1, p2) = (v1.as_ptr(), v2.as_ptr());
+        let mut acc = _mm512_setzero_si512();
+        let mut i = 0;
+        // SAFETY: every load reads 8 words starting at `i`, with `i + 8 <= len`.
+        unsafe {
+            while i + 8 <= len {
+                let a = _mm512_loadu_si512(p1.add(i) as *const __m512i);
+                let b = _mm512_loadu_si512(p2.add(i) as *const __m512i);
+                acc = _mm512_add_epi64(acc, _mm512_popcnt_epi64(_mm512_xor_si512(a, b)));
+                i += 8;
+            }
+        }
+        _mm512_reduce_add_epi64(acc) as u32 + scalar::hamming(&v1[i..len], &v2[i..len])
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    use super::{scalar, Kernels};
+
+    // Only reachable through `NEON`, which `available` hands out after detecting NEON.
+    pub static NEON: Kernels = Kernels {
+        name: "neon",
+        dot_f32: |v1, v2| unsafe { dot_f32_neon(v1, v2) },
+        squared_l2: |v1, v2| unsafe { squared_l2_neon(v1, v2) },
+        manhattan: |v1, v2| unsafe { manhattan_neon(v1, v2) },
+        dot_i8: |v1, v2| unsafe { dot_i8_neon(v1, v2) },
+        hamming: |v1, v2| unsafe { hamming_neon(v1, v2) },
+    };
+
+    #[target_feature(enable = "neon")]
+    fn dot_f32_neon(v1: &[f32], v2: &[f32]) -> f32 {
+        let len = v1.len().min(v2.len());
+        let (p1, p2) = (v1.as_ptr(), v2.as_ptr());
+        let mut acc = [vdupq_n_f32(0.0); 4];
+        let mut i = 0;
+        // SAFETY: every load reads 4 floats starting at `i`, with `i + 4 <= len`.
+        unsafe {
+            while i + 16 <= len {
+                for (j, acc) in acc.iter_mut().enumerate() {
+                    let offset = i + j * 4;
+                    *acc = vfmaq_f32(*acc, vld1q_f32(p1.add(offset)), vld1q_f32(p2.add(offset)));
+                }
+                i += 16;
+            }
+            while i + 4 <= len {
+                acc[0] = vfmaq_f32(acc[0], vld1q_f32(p1.add(i)), vld1q_f32(p2.add(i)));
+                i += 4;
+            }
+        }
+        let sum = vaddq_f32(vaddq_f32(acc[0], acc[1]), vaddq_f32(acc[2], acc[3]));
+        vaddvq_f32(sum) + scalar::dot_f32(&v1[i..len], &v2[i..len])
+    }
+
+    #[target_feature(enable = "neon")]
+    fn squared_l2_neon(v1: &[f32], v2: &[f32]) -> f32 {
+        let len = v1.len().min(v2.len());
+        let (p1, p2) = (v1.as_ptr(), v2.as_ptr());
+        let mut acc = vdupq_n_f32(0.0);
+        let mut i = 0;
+        // SAFETY: every load reads 4 floats starting at `i`, with `i + 4 <= len`.
+        unsafe {
+            while i + 4 <= len {
+                let diff = vsubq_f32(vld1q_f32(p1.add(i)), vld1q_f32(p2.add(i)));
+                acc = vfmaq_f32(acc, diff, diff);
+                i += 4;
+            }
+        }
+        vaddvq_f32(acc) + scalar::squared_l2(&v1[i..len], &v2[i..len])
+    }
+
+    #[target_feature(enable = "neon")]
+    fn manhattan_neon(v1: &[f32], v2: &[f32]) -> f32 {
+        let len = v1.len().min(v2.len());
+        let (p1, p2) = (v1.as_ptr(), v2.as_ptr());
+        let mut acc = vdupq_n_f32(0.0);
+        let mut i = 0;
+        // SAFETY: every load reads 4 floats starting at `i`, with `i + 4 <= len`.
+        unsafe {
+            while i + 4 <= len {
+                acc = vaddq_f32(acc, vabdq_f32(vld1q_f32(p1.add(i)), vld1q_f32(p2.add(i))));
+                i += 4;
+            }
+        }
+        vaddvq_f32(acc) + scalar::manhattan(&v1[i..len], &v2[i..len])
+    }
+
+    #[target_feature(enable = "neon")]
+    fn dot_i8_neon(v1: &[i8], v2: &[i8]) -> i32 {
+        let len = v1.len().min(v2.len());
+        let (p1, p2) = (v1.as_ptr(), v2.as_ptr());
+        let mut acc = vdupq_n_s32(0);
+        let mut i = 0;
+        // SAFETY: every load reads 16 bytes starting at `i`, with `i + 16 <= len`.
+        unsafe {
+            while i + 16 <= len {
+                let a = vld1q_s8(p1.add(i));
+                let b = vld1q_s8(p2.add(i));
+                // Products of two i8 fit in i16; pairwise-add them into the i32 lanes.
+                acc = vpadalq_s16(acc, vmull_s8(vget_low_s8(a), vget_low_s8(b)));
+                acc = vpadalq_s16(acc, vmull_high_s8(a, b));
+                i += 16;
+            }
+        }
+        vaddvq_s32(acc) + scalar::dot_i8(&v1[i..len], &v2[i..len])
+    }
+
+    #[target_feature(enable = "neon")]
+    fn hamming_neon(v1: &[u64], v2: &[u64]) -> u32 {
+        let len = v1.len().min(v2.len());
+        let (p1, p2) = (v1.as_ptr(), v2.as_ptr());
+        let mut total = 0u32;
+        let mut i = 0;
+        // SAFETY: every load reads 2 words starting at `i`, with `i + 2 <= len`.
+        unsafe {
+            while i + 2 <= len {
+                let x = veorq_u64(vld1q_u64(p1.add(i)), vld1q_u64(p2.add(i)));
+                total += vaddlvq_u8(vcntq_u8(vreinterpretq_u8_u64(x))) as u32;
+                i += 2;
+            }
+        }
+        total + scalar::hamming(&v1[i..len], &v2[i..len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // Pairs of equally long vectors, long enough to cover unrolled loops and tails.
+    fn f32_pairs() -> impl Strategy<Value = (Vec<f32>, Vec<f32>)> {
+        (0usize..300).prop_flat_map(|len| {
+            (prop::collection::vec(-1.0f32..1.0, len), prop::collection::vec(-1.0f32..1.0, len))
+        })
+    }
+
+    fn i8_pairs() -> impl Strategy<Value = (Vec<i8>, Vec<i8>)> {
+        (0usize..300).prop_flat_map(|len| {
+            (prop::collection::vec(any::<i8>(), len), prop::collection::vec(any::<i8>(), len))
+        })
+    }
+
+    fn u64_pairs() -> impl Strategy<Value = (Vec<u64>, Vec<u64>)> {
+        (0usize..40).prop_flat_map(|len| {
+            (prop::collection::vec(any::<u64>(), len), prop::collection::vec(any::<u64>(), len))
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn dot_f32_matches_scalar((v1, v2) in f32_pairs()) {
+            let expected = scalar::dot_f32(&v1, &v2);
+            for kernels in available() {
+                let actual = (kernels.dot_f32)(&v1, &v2);
+                // Summation order differs between kernels, so allow rounding error.
+                let tolerance = 1e-5 * (v1.len() as f32 + 1.0);
+                prop_assert!((actual - expected).abs() <= tolerance, "{}: {} vs {}", kernels.name, actual, expected);
+            }
+        }
+
+        #[test]
+        fn distances_match_scalar((v1, v2) in f32_pairs()) {
+            let (l2, l1) = (scalar::squared_l2(&v1, &v2), scalar::manhattan(&v1, &v2));
+            let tolerance = 1e-5 * (v1.len() as f32 + 1.0);
+            for kernels in available() {
+                let (actual_l2, actual_l1) = ((kernels.squared_l2)(&v1, &v2), (kernels.manhattan)(&v1, &v2));
+                prop_assert!((actual_l2 - l2).abs() <= tolerance, "{}: {} vs {}", kernels.name, actual_l2, l2);
+                prop_assert!((actual_l1 - l1).abs() <= tolerance, "{}: {} vs {}", kernels.name, actual_l1, l1);
+            }
+        }
+
+        #[test]
+        fn dot_i8_matches_scalar((v1, v2) in i8_pairs()) {
+            let expected = scalar::dot_i8(&v1, &v2);
+            for kernels in available() {
+                prop_assert_eq!((kernels.dot_i8)(&v1, &v2), expected, "{}", kernels.name);
+            }
+        }
+
+        #[test]
+        fn hamming_matches_scalar((v1, v2) in u64_pairs()) {
+            let expected = scalar::hamming(&v1, &v2);
+            for kernels in available() {
+                prop_assert_eq!((kernels.hamming)(&v1, &v2), expected, "{}", kernels.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_dispatch_picks_an_available_set() {
+        let names: Vec<_> = available().iter().map(|kernels| kernels.name).collect();
+        assert_eq!(names[0], "scalar");
+        assert!(names.contains(&kernels().name));
+    }
+}